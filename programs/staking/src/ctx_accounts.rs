@@ -19,7 +19,7 @@ pub struct Initialize<'info> {
         seeds = [b"staking_pool", stake_mint.key().as_ref()],
         bump
     )]
-    pub staking_pool: Account<'info, StakingPool>,
+    pub staking_pool: Box<Account<'info, StakingPool>>,
     
     pub stake_mint: InterfaceAccount<'info, Mint>,
     
@@ -64,7 +64,7 @@ pub struct Stake<'info> {
         seeds = [b"staking_pool", stake_mint.key().as_ref()],
         bump,
     )]
-    pub staking_pool: Account<'info, StakingPool>,
+    pub staking_pool: Box<Account<'info, StakingPool>>,
     
     #[account(
         init_if_needed,
@@ -103,6 +103,40 @@ pub struct Stake<'info> {
     )]
     pub stake_vault: InterfaceAccount<'info, TokenAccount>,
     
+    // Required only while the allow-list is enabled and no Merkle proof is given
+    #[account(
+        seeds = [
+            b"allowlist",
+            staking_pool.key().as_ref(),
+            allowlist_entry.wallet.as_ref()
+        ],
+        bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    
+    /// CHECK: Deny-list PDA of the staker, must not exist
+    #[account(
+        seeds = [
+            b"denylist",
+            staking_pool.key().as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub denylist_entry: AccountInfo<'info>,
+    
+    // Required only with a referrer, registered with register_referrer
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            staking_pool.key().as_ref(),
+            referrer_account.referrer.as_ref()
+        ],
+        bump
+    )]
+    pub referrer_account: Option<Account<'info, ReferrerAccount>>,
+    
     pub stake_mint: InterfaceAccount<'info, Mint>,
     
     pub system_program: Program<'info, System>,
@@ -111,6 +145,111 @@ pub struct Stake<'info> {
     pub rent: Sysvar<'info, Rent>,
 }
 
+#[derive(Accounts)]
+#[instruction(beneficiary: Pubkey)]
+pub struct StakeFor<'info> {
+    #[account(mut)]
+    pub funder: Signer<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+    
+    // Created by the beneficiary when approving the funder
+    #[account(
+        mut,
+        seeds = [
+            b"user_staking",
+            beneficiary.as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump,
+        constraint = user_staking_account.stake_for_funder == funder.key() @ StakingError::StakeForNotApproved
+    )]
+    pub user_staking_account: Account<'info, UserStakingAccount>,
+    
+    /// CHECK: This account will be initialized manually in the instruction
+    #[account(
+        mut,
+        constraint = stake_entry.to_account_info().owner == &system_program::ID @ StakingError::StakeEntryAlreadyExists,
+        constraint = stake_entry.to_account_info().lamports() == 0 @ StakingError::StakeEntryAlreadyExists
+    )]
+    pub stake_entry: AccountInfo<'info>,
+    
+    #[account(
+        mut,
+        token::mint = stake_mint,
+        token::authority = funder,
+        token::token_program = token_program,
+    )]
+    pub funder_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"stake_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub stake_vault: InterfaceAccount<'info, TokenAccount>,
+    
+    // Required only while the allow-list is enabled and no Merkle proof is given
+    #[account(
+        seeds = [
+            b"allowlist",
+            staking_pool.key().as_ref(),
+            allowlist_entry.wallet.as_ref()
+        ],
+        bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    
+    /// CHECK: Deny-list PDA of the beneficiary, must not exist
+    #[account(
+        seeds = [
+            b"denylist",
+            staking_pool.key().as_ref(),
+            beneficiary.as_ref()
+        ],
+        bump
+    )]
+    pub denylist_entry: AccountInfo<'info>,
+    
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+    
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct SetStakeForFunder<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserStakingAccount::INIT_SPACE,
+        seeds = [
+            b"user_staking",
+            user.key().as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump
+    )]
+    pub user_staking_account: Account<'info, UserStakingAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 #[instruction(stake_index: u64)]
 pub struct Unstake<'info> {
@@ -122,13 +261,13 @@ pub struct Unstake<'info> {
         seeds = [b"staking_pool", stake_mint.key().as_ref()],
         bump,
     )]
-    pub staking_pool: Account<'info, StakingPool>,
+    pub staking_pool: Box<Account<'info, StakingPool>>,
     
     #[account(
         mut,
         seeds = [
             b"user_staking",
-            user.key().as_ref(),
+            stake_entry.owner.as_ref(),
             staking_pool.key().as_ref()
         ],
         bump
@@ -139,17 +278,17 @@ pub struct Unstake<'info> {
         mut,
         seeds = [
             b"stake_entry",
-            user.key().as_ref(),
+            stake_entry.owner.as_ref(),
             staking_pool.key().as_ref(),
             &stake_index.to_le_bytes()
         ],
         bump,
-        constraint = stake_entry.owner == user.key() @ StakingError::Unauthorized
     )]
     pub stake_entry: Account<'info, StakeEntry>,
     
     #[account(
-        mut,
+        init_if_needed,
+        payer = user,
         associated_token::mint = stake_mint,
         associated_token::authority = user,
         associated_token::token_program = token_program,
@@ -184,6 +323,34 @@ pub struct Unstake<'info> {
         constraint = treasury_token_account.key() == staking_pool.treasury_address,
     )]
     pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Required only when the fee split routes part of the penalty to buyback
+    #[account(
+        mut,
+        constraint = buyback_token_account.key() == staking_pool.buyback_address @ StakingError::InvalidFeeDestination,
+    )]
+    pub buyback_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // Required only when the stake is held as a position NFT, the signer must hold it
+    #[account(mut)]
+    pub position_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        address = stake_entry.position_mint @ StakingError::InvalidPositionToken
+    )]
+    pub position_mint: Option<InterfaceAccount<'info, Mint>>,
+    
+    /// CHECK: Deny-list PDA of the signer, must not exist
+    #[account(
+        seeds = [
+            b"denylist",
+            staking_pool.key().as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub denylist_entry: AccountInfo<'info>,
     
     pub stake_mint: InterfaceAccount<'info, Mint>,
     
@@ -193,6 +360,94 @@ pub struct Unstake<'info> {
     pub rent: Sysvar<'info, Rent>,
 }
 
+#[derive(Accounts)]
+#[instruction(stake_index: u64)]
+pub struct SetAutoRenew<'info> {
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"stake_entry",
+            stake_entry.owner.as_ref(),
+            staking_pool.key().as_ref(),
+            &stake_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub stake_entry: Account<'info, StakeEntry>,
+
+    // Required only when the stake is held as a position NFT, the signer must hold it
+    pub position_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+#[instruction(stake_index: u64)]
+pub struct Withdraw<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"stake_entry",
+            stake_entry.owner.as_ref(),
+            staking_pool.key().as_ref(),
+            &stake_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub stake_entry: Account<'info, StakeEntry>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = stake_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"stake_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub stake_vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Required only when the stake is held as a position NFT, the signer must hold it
+    #[account(mut)]
+    pub position_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        address = stake_entry.position_mint @ StakingError::InvalidPositionToken
+    )]
+    pub position_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
 #[derive(Accounts)]
 pub struct CloseProgram<'info> {
     #[account(
@@ -201,7 +456,7 @@ pub struct CloseProgram<'info> {
         seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
         bump,
     )]
-    pub staking_pool: Account<'info, StakingPool>,
+    pub staking_pool: Box<Account<'info, StakingPool>>,
     
     pub authority: Signer<'info>,
     
@@ -233,7 +488,7 @@ pub struct ClaimAll<'info> {
         seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
         bump,
     )]
-    pub staking_pool: Account<'info, StakingPool>,
+    pub staking_pool: Box<Account<'info, StakingPool>>,
     
     #[account(
         mut,
@@ -262,6 +517,17 @@ pub struct ClaimAll<'info> {
     )]
     pub reward_vault: InterfaceAccount<'info, TokenAccount>,
     
+    /// CHECK: Deny-list PDA of the user, must not exist
+    #[account(
+        seeds = [
+            b"denylist",
+            staking_pool.key().as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub denylist_entry: AccountInfo<'info>,
+    
     pub stake_mint: InterfaceAccount<'info, Mint>,
     
     pub system_program: Program<'info, System>,
@@ -281,34 +547,1250 @@ pub struct GetTotalClaimableRewards<'info> {
         seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
         bump,
     )]
-    pub staking_pool: Account<'info, StakingPool>,
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        seeds = [
+            b"user_staking",
+            user.key().as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump
+    )]
+    pub user_staking_account: Account<'info, UserStakingAccount>,
     
     // Remaining accounts: StakeEntry accounts
 }
 
 #[derive(Accounts)]
-pub struct UpdateNormalizationK<'info> {
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    
+pub struct GetStakingCapacity<'info> {
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    // Optional, without it user_remaining is the full per user cap
+    #[account(
+        seeds = [
+            b"user_staking",
+            user_staking_account.owner.as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump
+    )]
+    pub user_staking_account: Option<Account<'info, UserStakingAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct GetVotingPower<'info> {
     #[account(
-        mut,
         seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
         bump,
     )]
-    pub staking_pool: Account<'info, StakingPool>,
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    // Remaining accounts: StakeEntry accounts of the user in index order
 }
 
 #[derive(Accounts)]
-pub struct UpdateDailyRate<'info> {
+pub struct UpdateVoterWeightRecord<'info> {
     #[account(mut)]
-    pub authority: Signer<'info>,
-    
+    pub user: Signer<'info>,
+
     #[account(
-        mut,
         seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
         bump,
-        constraint = staking_pool.authority == authority.key() @ StakingError::Unauthorized
+        constraint = staking_pool.governance_realm != Pubkey::default() @ StakingError::GovernanceNotConfigured
     )]
-    pub staking_pool: Account<'info, StakingPool>,
-}
\ No newline at end of file
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + VoterWeightRecord::INIT_SPACE,
+        seeds = [
+            b"voter-weight-record",
+            staking_pool.governance_realm.as_ref(),
+            staking_pool.governing_token_mint.as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub voter_weight_record: Account<'info, VoterWeightRecord>,
+
+    pub system_program: Program<'info, System>,
+
+    // Remaining accounts: StakeEntry accounts of the user in index order
+}
+
+#[derive(Accounts)]
+pub struct UpdateMaxVoterWeightRecord<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+        constraint = staking_pool.governance_realm != Pubkey::default() @ StakingError::GovernanceNotConfigured
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + MaxVoterWeightRecord::INIT_SPACE,
+        seeds = [
+            b"max-voter-weight-record",
+            staking_pool.governance_realm.as_ref(),
+            staking_pool.governing_token_mint.as_ref()
+        ],
+        bump
+    )]
+    pub max_voter_weight_record: Account<'info, MaxVoterWeightRecord>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FundRewardVault<'info> {
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+        constraint = staking_pool.reward_funder == Pubkey::default()
+            || staking_pool.reward_funder == funder.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        token::mint = stake_mint,
+        token::authority = funder,
+        token::token_program = token_program,
+    )]
+    pub funder_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct QueueExcessWithdrawal<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        seeds = [b"reward_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct CancelExcessWithdrawal<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawExcessRewards<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = treasury_token_account.key() == staking_pool.treasury_address,
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct QueueParameterChange<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+        constraint = staking_pool.authority == authority.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PendingChange::INIT_SPACE,
+        seeds = [
+            b"pending_change",
+            staking_pool.key().as_ref(),
+            &staking_pool.change_count.to_le_bytes()
+        ],
+        bump
+    )]
+    pub pending_change: Account<'info, PendingChange>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(change_id: u64)]
+pub struct CancelParameterChange<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+        constraint = staking_pool.authority == authority.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [
+            b"pending_change",
+            staking_pool.key().as_ref(),
+            &change_id.to_le_bytes()
+        ],
+        bump
+    )]
+    pub pending_change: Account<'info, PendingChange>,
+}
+
+#[derive(Accounts)]
+#[instruction(change_id: u64)]
+pub struct ExecuteParameterChange<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+        constraint = staking_pool.authority == authority.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [
+            b"pending_change",
+            staking_pool.key().as_ref(),
+            &change_id.to_le_bytes()
+        ],
+        bump
+    )]
+    pub pending_change: Account<'info, PendingChange>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    // Required only for ParameterChange::Treasury
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // Required only for ParameterChange::FeeRouting with a buyback wallet
+    pub buyback_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+}
+#[derive(Accounts)]
+pub struct AttachBoostNft<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user_staking",
+            user.key().as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump
+    )]
+    pub user_staking_account: Account<'info, UserStakingAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + BoostAccount::INIT_SPACE,
+        seeds = [
+            b"boost",
+            user.key().as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump
+    )]
+    pub boost_account: Account<'info, BoostAccount>,
+
+    pub nft_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = nft_mint,
+        token::authority = user,
+        token::token_program = token_program,
+    )]
+    pub user_nft_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = user,
+        token::mint = nft_mint,
+        token::authority = nft_vault,
+        token::token_program = token_program,
+        seeds = [b"boost_nft_vault", boost_account.key().as_ref()],
+        bump,
+    )]
+    pub nft_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+
+    // Remaining accounts: all StakeEntry accounts of the user in index order
+}
+
+#[derive(Accounts)]
+pub struct DetachBoostNft<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user_staking",
+            user.key().as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump
+    )]
+    pub user_staking_account: Account<'info, UserStakingAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"boost",
+            user.key().as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump,
+        constraint = boost_account.nft_mint != Pubkey::default() @ StakingError::NoBoostNftAttached
+    )]
+    pub boost_account: Account<'info, BoostAccount>,
+
+    #[account(
+        address = boost_account.nft_mint @ StakingError::InvalidBoostNft
+    )]
+    pub nft_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = nft_mint,
+        token::authority = user,
+        token::token_program = token_program,
+    )]
+    pub user_nft_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"boost_nft_vault", boost_account.key().as_ref()],
+        bump,
+    )]
+    pub nft_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    // Remaining accounts: all StakeEntry accounts of the user in index order
+}
+
+#[derive(Accounts)]
+pub struct LockBoostTokens<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user_staking",
+            user.key().as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump
+    )]
+    pub user_staking_account: Account<'info, UserStakingAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + BoostAccount::INIT_SPACE,
+        seeds = [
+            b"boost",
+            user.key().as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump
+    )]
+    pub boost_account: Account<'info, BoostAccount>,
+
+    #[account(
+        address = staking_pool.boost_config.boost_mint @ StakingError::BoostNotConfigured
+    )]
+    pub boost_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = boost_mint,
+        token::authority = user,
+        token::token_program = token_program,
+    )]
+    pub user_boost_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        token::mint = boost_mint,
+        token::authority = boost_vault,
+        token::token_program = token_program,
+        seeds = [b"boost_vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub boost_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+
+    // Remaining accounts: all StakeEntry accounts of the user in index order
+}
+
+#[derive(Accounts)]
+pub struct UnlockBoostTokens<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user_staking",
+            user.key().as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump
+    )]
+    pub user_staking_account: Account<'info, UserStakingAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"boost",
+            user.key().as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump,
+    )]
+    pub boost_account: Account<'info, BoostAccount>,
+
+    #[account(
+        address = boost_vault.mint @ StakingError::InvalidBoostConfig
+    )]
+    pub boost_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = boost_mint,
+        token::authority = user,
+        token::token_program = token_program,
+    )]
+    pub user_boost_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"boost_vault", staking_pool.key().as_ref()],
+        bump,
+    )]
+    pub boost_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    // Remaining accounts: all StakeEntry accounts of the user in index order
+}
+
+#[derive(Accounts)]
+pub struct RefreshBoost<'info> {
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user_staking",
+            user_staking_account.owner.as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump
+    )]
+    pub user_staking_account: Account<'info, UserStakingAccount>,
+
+    #[account(
+        seeds = [
+            b"boost",
+            user_staking_account.owner.as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump,
+    )]
+    pub boost_account: Account<'info, BoostAccount>,
+
+    // Remaining accounts: all StakeEntry accounts of the user in index order
+}
+
+#[derive(Accounts)]
+#[instruction(stake_index: u64)]
+pub struct MintPositionNft<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        seeds = [
+            b"user_staking",
+            user.key().as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump
+    )]
+    pub user_staking_account: Account<'info, UserStakingAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"stake_entry",
+            user.key().as_ref(),
+            staking_pool.key().as_ref(),
+            &stake_index.to_le_bytes()
+        ],
+        bump,
+        constraint = stake_entry.owner == user.key() @ StakingError::Unauthorized,
+        constraint = stake_entry.position_mint == Pubkey::default() @ StakingError::PositionAlreadyMinted
+    )]
+    pub stake_entry: Account<'info, StakeEntry>,
+
+    #[account(
+        init,
+        payer = user,
+        seeds = [b"position_mint", stake_entry.key().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = staking_pool,
+        mint::token_program = token_program,
+        extensions::metadata_pointer::authority = staking_pool,
+        extensions::metadata_pointer::metadata_address = position_mint,
+    )]
+    pub position_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = position_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+    )]
+    pub position_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+    #[account(address = anchor_spl::token_2022::ID)]
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(stake_index: u64)]
+pub struct ClaimPosition<'info> {
+    #[account(mut)]
+    pub holder: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"stake_entry",
+            stake_entry.owner.as_ref(),
+            staking_pool.key().as_ref(),
+            &stake_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub stake_entry: Account<'info, StakeEntry>,
+
+    #[account(
+        token::mint = stake_entry.position_mint,
+        token::authority = holder,
+        constraint = position_token_account.amount == 1 @ StakingError::InvalidPositionToken
+    )]
+    pub position_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = holder,
+        associated_token::mint = stake_mint,
+        associated_token::authority = holder,
+        associated_token::token_program = token_program,
+    )]
+    pub holder_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: Deny-list PDA of the holder, must not exist
+    #[account(
+        seeds = [
+            b"denylist",
+            staking_pool.key().as_ref(),
+            holder.key().as_ref()
+        ],
+        bump
+    )]
+    pub denylist_entry: AccountInfo<'info>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeLiquidMint<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"liquid_mint", staking_pool.key().as_ref()],
+        bump,
+        mint::decimals = stake_mint.decimals,
+        mint::authority = staking_pool,
+        mint::token_program = token_program,
+    )]
+    pub liquid_mint: InterfaceAccount<'info, Mint>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct CompoundLiquid<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        address = staking_pool.liquid_mint @ StakingError::LiquidStakingDisabled
+    )]
+    pub liquid_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"stake_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub stake_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct StakeLiquid<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        address = staking_pool.liquid_mint @ StakingError::LiquidStakingDisabled
+    )]
+    pub liquid_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = stake_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = liquid_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+    )]
+    pub user_liquid_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: UserStakingAccount PDA of the staker, read for the per user cap when it exists
+    #[account(
+        seeds = [
+            b"user_staking",
+            user.key().as_ref(),
+            staking_pool.key().as_ref()
+        ],
+        bump
+    )]
+    pub user_staking_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"stake_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub stake_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Required only while the allow-list is enabled and no Merkle proof is given
+    #[account(
+        seeds = [
+            b"allowlist",
+            staking_pool.key().as_ref(),
+            allowlist_entry.wallet.as_ref()
+        ],
+        bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+
+    /// CHECK: Deny-list PDA of the staker, must not exist
+    #[account(
+        seeds = [
+            b"denylist",
+            staking_pool.key().as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub denylist_entry: AccountInfo<'info>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemLiquid<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        address = staking_pool.liquid_mint @ StakingError::LiquidStakingDisabled
+    )]
+    pub liquid_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = stake_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = liquid_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+    )]
+    pub user_liquid_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"stake_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub stake_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct RequestRedeemLiquid<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        address = staking_pool.liquid_mint @ StakingError::LiquidStakingDisabled
+    )]
+    pub liquid_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = liquid_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+    )]
+    pub user_liquid_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + LiquidUnbonding::INIT_SPACE,
+        seeds = [
+            b"liquid_unbonding",
+            staking_pool.key().as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub liquid_unbonding: Account<'info, LiquidUnbonding>,
+
+    #[account(
+        mut,
+        seeds = [b"stake_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub stake_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawLiquid<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"liquid_unbonding",
+            staking_pool.key().as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub liquid_unbonding: Account<'info, LiquidUnbonding>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = stake_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"stake_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub stake_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct GetLiquidExchangeRate<'info> {
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateAllowlist<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+        constraint = staking_pool.authority == authority.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+}
+
+#[derive(Accounts)]
+#[instruction(wallet: Pubkey)]
+pub struct AddAllowlistEntry<'info> {
+    #[account(mut)]
+    pub gatekeeper: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+        constraint = staking_pool.gatekeeper == gatekeeper.key()
+            || staking_pool.authority == gatekeeper.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        init,
+        payer = gatekeeper,
+        space = 8 + AllowlistEntry::INIT_SPACE,
+        seeds = [b"allowlist", staking_pool.key().as_ref(), wallet.as_ref()],
+        bump
+    )]
+    pub allowlist_entry: Account<'info, AllowlistEntry>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(wallet: Pubkey)]
+pub struct RemoveAllowlistEntry<'info> {
+    #[account(mut)]
+    pub gatekeeper: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+        constraint = staking_pool.gatekeeper == gatekeeper.key()
+            || staking_pool.authority == gatekeeper.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        close = gatekeeper,
+        seeds = [b"allowlist", staking_pool.key().as_ref(), wallet.as_ref()],
+        bump
+    )]
+    pub allowlist_entry: Account<'info, AllowlistEntry>,
+}
+
+#[derive(Accounts)]
+#[instruction(wallet: Pubkey)]
+pub struct AddDenylistEntry<'info> {
+    #[account(mut)]
+    pub gatekeeper: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+        constraint = staking_pool.gatekeeper == gatekeeper.key()
+            || staking_pool.authority == gatekeeper.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        init,
+        payer = gatekeeper,
+        space = 8 + DenylistEntry::INIT_SPACE,
+        seeds = [b"denylist", staking_pool.key().as_ref(), wallet.as_ref()],
+        bump
+    )]
+    pub denylist_entry: Account<'info, DenylistEntry>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(wallet: Pubkey)]
+pub struct RemoveDenylistEntry<'info> {
+    #[account(mut)]
+    pub gatekeeper: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+        constraint = staking_pool.gatekeeper == gatekeeper.key()
+            || staking_pool.authority == gatekeeper.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        close = gatekeeper,
+        seeds = [b"denylist", staking_pool.key().as_ref(), wallet.as_ref()],
+        bump
+    )]
+    pub denylist_entry: Account<'info, DenylistEntry>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+
+    #[account(
+        seeds = [b"staking_pool", staking_pool.stake_mint.as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        init,
+        payer = referrer,
+        space = 8 + ReferrerAccount::INIT_SPACE,
+        seeds = [b"referrer", staking_pool.key().as_ref(), referrer.key().as_ref()],
+        bump
+    )]
+    pub referrer_account: Account<'info, ReferrerAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [b"referrer", staking_pool.key().as_ref(), referrer.key().as_ref()],
+        bump
+    )]
+    pub referrer_account: Account<'info, ReferrerAccount>,
+
+    /// CHECK: Deny-list PDA of the referrer, must not exist
+    #[account(
+        seeds = [
+            b"denylist",
+            staking_pool.key().as_ref(),
+            referrer.key().as_ref()
+        ],
+        bump
+    )]
+    pub denylist_entry: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = referrer,
+        associated_token::mint = stake_mint,
+        associated_token::authority = referrer,
+        associated_token::token_program = token_program,
+    )]
+    pub referrer_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    // Remaining accounts: StakeEntry accounts referred by the signer
+}
+
+#[derive(Accounts)]
+pub struct CrankDailyRate<'info> {
+    pub cranker: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_pool", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub staking_pool: Box<Account<'info, StakingPool>>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault", stake_mint.key().as_ref()],
+        bump,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Optional, receives the tip
+    #[account(
+        mut,
+        token::mint = stake_mint,
+        token::token_program = token_program,
+    )]
+    pub cranker_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub stake_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
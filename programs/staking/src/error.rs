@@ -31,4 +31,97 @@ pub enum StakingError {
     StakeEntryAlreadyExists,
     #[msg("Invalid normalization K value")]
     InvalidNormalizationK,
-}
\ No newline at end of file
+    #[msg("Invalid amount")]
+    InvalidAmount,
+    #[msg("Amount exceeds excess rewards")]
+    ExceedsExcessRewards,
+    #[msg("Withdrawal already queued")]
+    WithdrawalAlreadyQueued,
+    #[msg("No pending withdrawal")]
+    NoPendingWithdrawal,
+    #[msg("Timelock not expired")]
+    TimelockNotExpired,
+    #[msg("Invalid tier weights")]
+    InvalidTierWeights,
+    #[msg("Invalid penalty")]
+    InvalidPenalty,
+    #[msg("Invalid emission schedule")]
+    InvalidEmissionSchedule,
+    #[msg("Invalid timelock delay")]
+    InvalidTimelockDelay,
+    #[msg("Invalid treasury account")]
+    InvalidTreasuryAccount,
+    #[msg("Fee split must add up to 10000 basis points")]
+    InvalidFeeSplit,
+    #[msg("Invalid fee destination account")]
+    InvalidFeeDestination,
+    #[msg("Past daily rates cannot be changed")]
+    RetroactiveRateChange,
+    #[msg("Too many daily rate overrides")]
+    TooManyRateOverrides,
+    #[msg("All stake entries of the user must be passed")]
+    MissingStakeEntries,
+    #[msg("Boost not configured")]
+    BoostNotConfigured,
+    #[msg("Invalid boost configuration")]
+    InvalidBoostConfig,
+    #[msg("Invalid boost NFT")]
+    InvalidBoostNft,
+    #[msg("Boost NFT already attached")]
+    BoostNftAlreadyAttached,
+    #[msg("No boost NFT attached")]
+    NoBoostNftAttached,
+    // No longer returned, voting power is clamped at the horizon
+    #[msg("Stake ends after the voting power horizon")]
+    VotingHorizonExceeded,
+    #[msg("Voting power is not available for this time")]
+    InvalidVotingPowerTime,
+    #[msg("Governance realm not configured")]
+    GovernanceNotConfigured,
+    #[msg("Position NFT already minted for this stake")]
+    PositionAlreadyMinted,
+    #[msg("Stake is held as a position NFT")]
+    PositionTokenized,
+    #[msg("Invalid position NFT account")]
+    InvalidPositionToken,
+    #[msg("Liquid staking is not enabled")]
+    LiquidStakingDisabled,
+    #[msg("Unbonding cooldown is set, use request_unstake or request_redeem_liquid")]
+    UnbondingRequired,
+    #[msg("Invalid unbonding cooldown")]
+    InvalidUnbondingCooldown,
+    #[msg("No unbonding stake to withdraw")]
+    NoPendingUnbonding,
+    #[msg("Unbonding cooldown has not passed")]
+    UnbondingNotFinished,
+    #[msg("Stake lock has already ended")]
+    StakeMatured,
+    #[msg("Invalid claim epoch")]
+    InvalidClaimEpoch,
+    #[msg("Stake amount is below the minimum")]
+    BelowMinimumStake,
+    #[msg("Stake exceeds the per user cap")]
+    UserStakeCapExceeded,
+    #[msg("Stake exceeds the tier cap")]
+    TierCapExceeded,
+    #[msg("Stake exceeds the pool cap")]
+    PoolCapExceeded,
+    #[msg("Invalid staking caps")]
+    InvalidStakingCaps,
+    #[msg("Wallet is not on the allow-list")]
+    NotAllowlisted,
+    #[msg("Wallet is on the deny-list")]
+    WalletDenied,
+    #[msg("Invalid referrer")]
+    InvalidReferrer,
+    #[msg("Referrer is not registered")]
+    ReferrerNotRegistered,
+    #[msg("Invalid referral share")]
+    InvalidReferralBps,
+    #[msg("Invalid rate smoothing")]
+    InvalidRateSmoothing,
+    #[msg("Too many weight changes")]
+    TooManyWeightChanges,
+    #[msg("Beneficiary has not approved the funder")]
+    StakeForNotApproved,
+}
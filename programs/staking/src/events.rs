@@ -1,4 +1,6 @@
 use anchor_lang::prelude::*;
+use crate::state::ParameterChange;
+use crate::utils::TIER_SLOTS;
 
 // Events
 #[event]
@@ -10,6 +12,23 @@ pub struct StakeEvent {
 }
 
 #[event]
+pub struct StakeForEvent {
+    pub funder: Pubkey,
+    pub beneficiary: Pubkey,
+    pub amount: u64,
+    pub duration_months: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StakeForFunderUpdatedEvent {
+    pub user: Pubkey,
+    pub funder: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+#[allow(dead_code)] // not emitted by the current instructions
 pub struct ClaimEvent {
     pub user: Pubkey,
     pub amount: u64,
@@ -21,6 +40,7 @@ pub struct UnstakeEvent {
     pub user: Pubkey,
     pub amount: u64,
     pub rewards: u64,
+    pub bonus: u64,
     pub timestamp: i64,
 }
 
@@ -28,6 +48,7 @@ pub struct UnstakeEvent {
 pub struct ClaimAllEvent {
     pub user: Pubkey,
     pub total_amount: u64,
+    pub bonus_amount: u64,
     pub stakes_count: u64,
     pub timestamp: i64,
 }
@@ -37,4 +58,248 @@ pub struct NormalizationKUpdatedEvent {
     pub old_k: u128,
     pub new_k: u128,
     pub timestamp: i64,
-}
\ No newline at end of file
+}
+
+#[event]
+pub struct RewardFundedEvent {
+    pub funder: Pubkey,
+    pub amount: u64,
+    pub total_funded: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ExcessWithdrawalQueuedEvent {
+    pub amount: u64,
+    pub eta: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ExcessWithdrawalCancelledEvent {
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ExcessWithdrawalExecutedEvent {
+    pub amount: u64,
+    pub total_funded: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ParameterChangeQueuedEvent {
+    pub change_id: u64,
+    pub change: ParameterChange,
+    pub eta: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ParameterChangeCancelledEvent {
+    pub change_id: u64,
+    pub change: ParameterChange,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ParameterChangeExecutedEvent {
+    pub change_id: u64,
+    pub change: ParameterChange,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DailyRateOverriddenEvent {
+    pub day_index: u64,
+    pub computed_rate: u64,
+    pub old_rate: u64,
+    pub new_rate: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PenaltyDistributedEvent {
+    pub user: Pubkey,
+    pub treasury_amount: u64,
+    pub buyback_amount: u64,
+    pub reward_vault_amount: u64,
+    pub bonus_pot_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BoostUpdatedEvent {
+    pub user: Pubkey,
+    pub nft_mint: Pubkey,
+    pub locked_amount: u64,
+    pub old_boost_bps: u16,
+    pub new_boost_bps: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionMintedEvent {
+    pub owner: Pubkey,
+    pub stake_index: u64,
+    pub position_mint: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionClaimEvent {
+    pub holder: Pubkey,
+    pub position_mint: Pubkey,
+    pub amount: u64,
+    pub bonus_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LiquidStakeEvent {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub receipt_amount: u64,
+    pub exchange_rate: u128,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LiquidRedeemEvent {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub receipt_amount: u64,
+    pub exchange_rate: u128,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LiquidRedeemRequestedEvent {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub receipt_amount: u64,
+    pub exchange_rate: u128,
+    pub unlock_time: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LiquidWithdrawEvent {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LiquidCompoundedEvent {
+    pub rewards: u64,
+    pub liquid_staked: u64,
+    pub exchange_rate: u128,
+    pub timestamp: i64,
+}
+#[event]
+pub struct UnstakeRequestedEvent {
+    pub user: Pubkey,
+    pub stake_index: u64,
+    pub amount: u64,
+    pub rewards: u64,
+    pub bonus: u64,
+    pub unlock_time: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawEvent {
+    pub user: Pubkey,
+    pub stake_index: u64,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AutoRenewUpdatedEvent {
+    pub user: Pubkey,
+    pub stake_index: u64,
+    pub auto_renew: bool,
+    pub lock_end: i64, // 0 while renewing
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AllowlistUpdatedEvent {
+    pub enabled: bool,
+    pub merkle_root: [u8; 32],
+    pub gatekeeper: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AllowlistEntryUpdatedEvent {
+    pub wallet: Pubkey,
+    pub added: bool, // false - removed
+    pub gatekeeper: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DenylistEntryUpdatedEvent {
+    pub wallet: Pubkey,
+    pub added: bool, // false - removed
+    pub gatekeeper: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReferrerRegisteredEvent {
+    pub referrer: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReferralRecordedEvent {
+    pub referrer: Pubkey,
+    pub user: Pubkey,
+    pub stake_index: u64,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReferralRewardsClaimedEvent {
+    pub referrer: Pubkey,
+    pub amount: u64,
+    pub stakes_count: u64,
+    pub timestamp: i64,
+}
+
+// Final inputs and outputs of a day's rate, emitted by the first rate update of a later day
+#[event]
+pub struct DailyRateFinalizedEvent {
+    pub day_index: u64,
+    pub rate: u64,          // rate rewards accrue at, the override if there is one
+    pub computed_rate: u64, // after rate smoothing
+    pub raw_rate: u64,
+    pub total_staked: u64,
+    pub earning_stake: u64,
+    pub available_rewards: u64,
+    pub normalization_k: u128,
+    pub tier_apy_bps: [u64; TIER_SLOTS], // 3, 6, 9, 12 months and flexible, without boosts
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CrankBudgetFundedEvent {
+    pub funder: Pubkey,
+    pub amount: u64,
+    pub crank_budget: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DailyRateCrankedEvent {
+    pub cranker: Pubkey,
+    pub day_index: u64, // latest computed day after the call
+    pub tip: u64,
+    pub timestamp: i64,
+}
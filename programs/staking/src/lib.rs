@@ -1,7 +1,21 @@
 #![allow(unexpected_cfgs)]
+// Only for the IDL instructions generated by #[program], they call the deprecated
+// AccountInfo::realloc and a lint attribute on the module does not reach them
+#![allow(deprecated)]
 
 use anchor_lang::prelude::*;
-use anchor_spl::token_2022::{transfer_checked, TransferChecked};
+use anchor_spl::token_2022::{
+    burn, close_account, mint_to, set_authority, transfer_checked, Burn, CloseAccount, MintTo,
+    SetAuthority, TransferChecked,
+};
+use anchor_spl::token_2022::spl_token_2022::instruction::AuthorityType;
+use anchor_lang::system_program;
+use anchor_spl::token_interface::spl_pod::optional_keys::OptionalNonZeroPubkey;
+use anchor_spl::token_interface::spl_token_metadata_interface::state::{Field, TokenMetadata};
+use anchor_spl::token_interface::{
+    token_metadata_initialize, token_metadata_update_field, Mint, TokenAccount, TokenInterface,
+    TokenMetadataInitialize, TokenMetadataUpdateField,
+};
 use solana_security_txt::security_txt;
 
 // Import modules
@@ -32,7 +46,15 @@ security_txt! {
 pub mod staking_program {
     use super::*;
 
-    pub fn initialize(ctx: Context<Initialize>, program_end_date: i64) -> Result<()> {
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        program_end_date: i64,
+        month_convention: MonthConvention,
+        claim_epoch: ClaimEpoch,
+        claim_epoch_offset: i64,
+    ) -> Result<()> {
+        utils::validate_claim_epoch(claim_epoch, claim_epoch_offset)?;
+
         let staking_pool = &mut ctx.accounts.staking_pool;
 
         staking_pool.authority = ctx.accounts.authority.key();
@@ -42,9 +64,60 @@ pub mod staking_program {
         staking_pool.last_update_time = Clock::get()?.unix_timestamp;
         staking_pool.program_start_time = Clock::get()?.unix_timestamp;
         staking_pool.program_end_date = program_end_date;
+        staking_pool.month_convention = month_convention;
+        staking_pool.claim_epoch = claim_epoch;
+        staking_pool.claim_epoch_offset = claim_epoch_offset;
+        staking_pool.accrual_mode = AccrualMode::WholeDays;
+        staking_pool.staking_caps = StakingCaps::default();
+        staking_pool.tier_staked = [0; utils::TIER_SLOTS];
         staking_pool.treasury_address = ctx.accounts.treasury_address.key();
         staking_pool.normalization_k = 250;
         staking_pool.daily_rates = vec![0; utils::MAX_DAILY_RATES];
+        staking_pool.total_funded = 0;
+        staking_pool.reward_funder = Pubkey::default();
+        staking_pool.timelock_delay = utils::DEFAULT_TIMELOCK_DELAY;
+        staking_pool.pending_withdrawal_amount = 0;
+        staking_pool.pending_withdrawal_eta = 0;
+        staking_pool.tier_weights = utils::DEFAULT_TIER_WEIGHTS;
+        staking_pool.flexible_weight = utils::DEFAULT_FLEXIBLE_WEIGHT;
+        staking_pool.matured_weight = 0;
+        staking_pool.max_penalty_percent = utils::DEFAULT_MAX_PENALTY_PERCENT;
+        staking_pool.emission_schedule = utils::DEFAULT_EMISSION_SCHEDULE;
+        staking_pool.change_count = 0;
+        staking_pool.daily_rate_overrides = Vec::new();
+        staking_pool.buyback_address = Pubkey::default();
+        staking_pool.fee_split = utils::DEFAULT_FEE_SPLIT;
+        staking_pool.penalty_mode = PenaltyMode::Route;
+        staking_pool.total_weighted_stake = 0;
+        staking_pool.bonus_per_weight = 0;
+        staking_pool.bonus_pot = 0;
+        staking_pool.total_bonus_distributed = 0;
+        staking_pool.boost_config = BoostConfig::default();
+        staking_pool.ve_bias = 0;
+        staking_pool.ve_slope = 0;
+        staking_pool.ve_permanent = 0;
+        staking_pool.total_matured = 0;
+        staking_pool.ve_checkpoint_time = staking_pool.program_start_time;
+        staking_pool.ve_slope_changes = [0; utils::VE_WEEKS];
+        staking_pool.governance_realm = Pubkey::default();
+        staking_pool.governing_token_mint = Pubkey::default();
+        staking_pool.liquid_mint = Pubkey::default();
+        staking_pool.liquid_staked = 0;
+        staking_pool.liquid_exchange_rate = utils::EXCHANGE_RATE_PRECISION;
+        staking_pool.liquid_compounded_day = 0;
+        staking_pool.unbonding_cooldown = 0;
+        staking_pool.total_unbonding = 0;
+        staking_pool.allowlist_enabled = false;
+        staking_pool.allowlist_root = [0; 32];
+        staking_pool.gatekeeper = Pubkey::default();
+        staking_pool.referral_bps = 0;
+        staking_pool.referral_pending = 0;
+        staking_pool.gap_fill_mode = GapFillMode::CarryForward;
+        staking_pool.crank_tip = 0;
+        staking_pool.crank_budget = 0;
+        staking_pool.rate_smoothing = RateSmoothing::default();
+        staking_pool.raw_daily_rates = vec![0; utils::MAX_DAILY_RATES];
+        staking_pool.weight_history = Vec::new();
 
         let avail_reward =
             utils::get_available_rewards(staking_pool, staking_pool.program_start_time)?;
@@ -53,199 +126,223 @@ pub mod staking_program {
         let initial_apy = utils::calculate_base_apy(1, avail_reward)?;
         let day_index = 0;
         staking_pool.daily_rates[day_index] = initial_apy;
+        staking_pool.raw_daily_rates[day_index] = initial_apy;
+        staking_pool.rate_snapshot = RateSnapshot {
+            day_index: day_index as u64,
+            total_staked: 0,
+            earning_stake: 1, // the initial rate is computed as if 1 token were staked
+            available_rewards: avail_reward,
+        };
 
         Ok(())
     }
 
-    pub fn stake(ctx: Context<Stake>, amount: u64, duration_months: u8) -> Result<()> {
-        require!(
-            duration_months == 3
-                || duration_months == 6
-                || duration_months == 9
-                || duration_months == 12,
-            StakingError::InvalidDuration
-        );
+    pub fn stake(
+        ctx: Context<Stake>,
+        amount: u64,
+        duration_months: u8,
+        allowlist_proof: Vec<[u8; 32]>,
+        referrer: Option<Pubkey>,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+        utils::validate_stake_access(
+            &accounts.staking_pool,
+            &accounts.user.key(),
+            accounts.allowlist_entry.as_deref(),
+            &accounts.denylist_entry,
+            &allowlist_proof,
+        )?;
+        if let Some(referrer) = referrer {
+            require!(referrer != accounts.user.key(), StakingError::InvalidReferrer);
+            require!(
+                accounts
+                    .referrer_account
+                    .as_ref()
+                    .is_some_and(|referrer_account| referrer_account.referrer == referrer),
+                StakingError::ReferrerNotRegistered
+            );
+        }
 
-        let clock = Clock::get()?;
-        let current_time = clock.unix_timestamp;
+        let delta = process_stake(
+            &accounts.user,
+            accounts.user.key(),
+            &mut accounts.staking_pool,
+            &mut accounts.user_staking_account,
+            &accounts.stake_entry,
+            &accounts.user_token_account,
+            &mut accounts.stake_vault,
+            &accounts.stake_mint,
+            &accounts.system_program,
+            &accounts.token_program,
+            ctx.program_id,
+            amount,
+            duration_months,
+            referrer.unwrap_or_default(),
+        )?;
 
-        // Check if reward pool has enough tokens for potential rewards
-        let available_rewards =
-            utils::get_available_rewards(&ctx.accounts.staking_pool, current_time)?;
+        if let (Some(referrer), Some(referrer_account)) = (referrer, accounts.referrer_account.as_mut()) {
+            referrer_account.referral_count = referrer_account
+                .referral_count
+                .checked_add(1)
+                .ok_or(StakingError::Overflow)?;
+            referrer_account.total_referred = referrer_account
+                .total_referred
+                .checked_add(delta)
+                .ok_or(StakingError::Overflow)?;
 
-        require!(available_rewards > 0, StakingError::RewardPoolExhausted);
+            emit!(ReferralRecordedEvent {
+                referrer,
+                user: accounts.user.key(),
+                stake_index: accounts.user_staking_account.stake_count - 1,
+                amount: delta,
+                timestamp: Clock::get()?.unix_timestamp,
+            });
+        }
 
-        // Transfer tokens from user to stake vault
-        let cpi_accounts = TransferChecked {
-            from: ctx.accounts.user_token_account.to_account_info(),
-            to: ctx.accounts.stake_vault.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
-            mint: ctx.accounts.stake_mint.to_account_info(),
-        };
+        Ok(())
+    }
 
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+    pub fn stake_for(
+        ctx: Context<StakeFor>,
+        beneficiary: Pubkey,
+        amount: u64,
+        duration_months: u8,
+        allowlist_proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+        // The gate applies to the beneficiary, not the funder
+        utils::validate_stake_access(
+            &accounts.staking_pool,
+            &beneficiary,
+            accounts.allowlist_entry.as_deref(),
+            &accounts.denylist_entry,
+            &allowlist_proof,
+        )?;
 
-        let before = ctx.accounts.stake_vault.amount;
-        transfer_checked(cpi_ctx, amount, utils::DECIMALS)?;
-        ctx.accounts.stake_vault.reload()?;
-        let after = ctx.accounts.stake_vault.amount;
-        let delta = after.checked_sub(before).unwrap_or(0);
+        // The funder pays tokens and rent, the position belongs to the beneficiary
+        let delta = process_stake(
+            &accounts.funder,
+            beneficiary,
+            &mut accounts.staking_pool,
+            &mut accounts.user_staking_account,
+            &accounts.stake_entry,
+            &accounts.funder_token_account,
+            &mut accounts.stake_vault,
+            &accounts.stake_mint,
+            &accounts.system_program,
+            &accounts.token_program,
+            ctx.program_id,
+            amount,
+            duration_months,
+            Pubkey::default(),
+        )?;
+
+        emit!(StakeForEvent {
+            funder: accounts.funder.key(),
+            beneficiary,
+            amount: delta,
+            duration_months,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
 
-        // Initialize or update user staking account
+    // Every entry has to be passed to claim_all and boost changes, so stake_for is limited
+    // to a single funder approved by the user
+    pub fn set_stake_for_funder(ctx: Context<SetStakeForFunder>, funder: Pubkey) -> Result<()> {
         let user_staking_account = &mut ctx.accounts.user_staking_account;
-        if user_staking_account.owner == Pubkey::default() {
-            user_staking_account.owner = ctx.accounts.user.key();
-            user_staking_account.stake_count = 0;
-            user_staking_account.total_staked = 0;
-            user_staking_account.total_claimed = 0;
-        }
+        user_staking_account.owner = ctx.accounts.user.key();
+        user_staking_account.stake_for_funder = funder;
 
-        // Auto-calculate stake index
-        let stake_index = user_staking_account.stake_count;
+        emit!(StakeForFunderUpdatedEvent {
+            user: ctx.accounts.user.key(),
+            funder,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
 
-        // Derive expected PDA and verify
-        let user_key = ctx.accounts.user.key();
-        let staking_pool_key = ctx.accounts.staking_pool.key();
-        let stake_index_bytes = stake_index.to_le_bytes();
-
-        let (expected_key, _bump) = Pubkey::find_program_address(
-            &[
-                b"stake_entry",
-                user_key.as_ref(),
-                staking_pool_key.as_ref(),
-                &stake_index_bytes,
-            ],
-            ctx.program_id,
-        );
+        Ok(())
+    }
 
+    pub fn unstake(ctx: Context<Unstake>, _stake_index: u64) -> Result<()> {
         require!(
-            ctx.accounts.stake_entry.key() == expected_key,
-            StakingError::InvalidStakeIndex
+            ctx.accounts.staking_pool.unbonding_cooldown == 0,
+            StakingError::UnbondingRequired
         );
 
-        // Create and initialize the stake entry account
-        let stake_entry_size = 8 + StakeEntry::INIT_SPACE;
-        let lamports = Rent::get()?.minimum_balance(stake_entry_size);
+        process_unstake(ctx, false)
+    }
 
-        let seeds = &[
-            b"stake_entry",
-            user_key.as_ref(),
-            staking_pool_key.as_ref(),
-            &stake_index_bytes,
-            &[_bump],
-        ];
-        let signer = &[&seeds[..]];
+    pub fn request_unstake(ctx: Context<Unstake>, _stake_index: u64) -> Result<()> {
+        process_unstake(ctx, true)
+    }
 
-        anchor_lang::solana_program::program::invoke_signed(
-            &anchor_lang::solana_program::system_instruction::create_account(
-                &ctx.accounts.user.key(),
-                &ctx.accounts.stake_entry.key(),
-                lamports,
-                stake_entry_size as u64,
-                ctx.program_id,
-            ),
-            &[
-                ctx.accounts.user.to_account_info(),
-                ctx.accounts.stake_entry.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-            signer,
+    pub fn set_auto_renew(ctx: Context<SetAutoRenew>, _stake_index: u64, auto_renew: bool) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let stake_entry = &mut ctx.accounts.stake_entry;
+        require!(stake_entry.is_active, StakingError::StakeNotActive);
+        require!(
+            !utils::is_flexible_stake(stake_entry),
+            StakingError::InvalidDuration
+        );
+        utils::validate_stake_authority(
+            stake_entry,
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_ref(),
         )?;
 
-        // Write the stake entry data
-        let mut data = ctx.accounts.stake_entry.try_borrow_mut_data()?;
+        if stake_entry.auto_renew == auto_renew {
+            return Ok(());
+        }
 
-        let stake_entry = StakeEntry {
-            owner: user_key,
-            stake_index,
-            amount: delta,
-            start_time: current_time,
-            duration_months,
-            last_claim_time: current_time,
-            last_claim_week: utils::get_week_number(
-                current_time,
-                ctx.accounts.staking_pool.program_start_time,
-            )?,
-            is_active: true,
-            total_claimed: 0,
-        };
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let month_convention = staking_pool.month_convention;
 
-        let mut writer = data.as_mut();
-        stake_entry.try_serialize(&mut writer)?;
+        // Matured days were not accrued, so renewal can only be turned on before the lock ends
+        if let Some(end_time) = utils::get_stake_end_time(stake_entry, month_convention) {
+            require!(current_time < end_time, StakingError::StakeMatured);
+        }
 
-        // Update user staking account
-        user_staking_account.stake_count = user_staking_account
-            .stake_count
-            .checked_add(1)
-            .ok_or(StakingError::Overflow)?;
-        user_staking_account.total_staked = user_staking_account
-            .total_staked
-            .checked_add(delta)
-            .ok_or(StakingError::Overflow)?;
+        utils::remove_stake_voting_power(staking_pool, stake_entry, current_time)?;
 
-        // Update staking pool
-        let staking_pool = &mut ctx.accounts.staking_pool;
-        staking_pool.total_staked = staking_pool
-            .total_staked
-            .checked_add(delta)
-            .ok_or(StakingError::Overflow)?;
+        // On cancel the current lock period becomes the last one
+        stake_entry.renewal_count =
+            utils::get_current_lock_period(stake_entry, month_convention, current_time);
+        stake_entry.auto_renew = auto_renew;
 
-        // Update daily rate for current day
-        utils::update_daily_rate(staking_pool, current_time)?;
+        utils::add_stake_voting_power(staking_pool, stake_entry, current_time)?;
 
-        emit!(StakeEvent {
+        emit!(AutoRenewUpdatedEvent {
             user: ctx.accounts.user.key(),
-            amount: delta,
-            duration_months,
+            stake_index: stake_entry.stake_index,
+            auto_renew,
+            lock_end: utils::get_stake_end_time(stake_entry, month_convention).unwrap_or(0),
             timestamp: current_time,
         });
 
         Ok(())
     }
-    pub fn unstake(ctx: Context<Unstake>, _stake_index: u64) -> Result<()> {
+
+    pub fn withdraw(ctx: Context<Withdraw>, _stake_index: u64) -> Result<()> {
         let clock = Clock::get()?;
         let current_time = clock.unix_timestamp;
 
         let stake_entry = &mut ctx.accounts.stake_entry;
-        require!(stake_entry.is_active, StakingError::StakeNotActive);
-
-        let staking_pool = &ctx.accounts.staking_pool;
-
-        // Calculate any remaining rewards
-        let rewards = utils::calculate_claimable_rewards(
+        let amount = stake_entry.unbonding_amount;
+        require!(amount > 0, StakingError::NoPendingUnbonding);
+        require!(
+            current_time >= stake_entry.unlock_time,
+            StakingError::UnbondingNotFinished
+        );
+        utils::validate_stake_authority(
             stake_entry,
-            staking_pool,
-            current_time,
-            true, // unstaking
+            &ctx.accounts.user.key(),
+            ctx.accounts.position_token_account.as_ref(),
         )?;
 
-        // Calculate penalty for early unstaking
-        let lock_duration_days = (stake_entry.duration_months as i64) * 30;
-        let elapsed_time = current_time - stake_entry.start_time;
-        let elapsed_days = elapsed_time / 86400; // seconds to days
-
-        let mut penalty_amount = 0u64;
-        let mut user_receive_amount = stake_entry.amount;
-
-        // Apply penalty if unstaking before lock period ends
-        if elapsed_days < lock_duration_days {
-            // Maximum penalty 20%, decreasing linearly to 0%
-            let penalty_rate = 20 * (lock_duration_days - elapsed_days) / lock_duration_days;
-            penalty_amount = stake_entry
-                .amount
-                .checked_mul(penalty_rate as u64)
-                .ok_or(StakingError::Overflow)?
-                .checked_div(100)
-                .ok_or(StakingError::Overflow)?;
-
-            user_receive_amount = stake_entry
-                .amount
-                .checked_sub(penalty_amount)
-                .ok_or(StakingError::Underflow)?;
-        }
-
-        // Transfer staked tokens back to user (minus penalty)
+        let staking_pool = &ctx.accounts.staking_pool;
         let seeds = &[
             b"stake_vault",
             staking_pool.stake_mint.as_ref(),
@@ -263,87 +360,43 @@ pub mod staking_program {
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
 
-        transfer_checked(cpi_ctx, user_receive_amount, utils::DECIMALS)?;
-
-        // Transfer penalty to treasury if any
-        if penalty_amount > 0 {
-            let penalty_cpi_accounts = TransferChecked {
-                from: ctx.accounts.stake_vault.to_account_info(),
-                to: ctx.accounts.treasury_token_account.to_account_info(),
-                authority: ctx.accounts.stake_vault.to_account_info(),
-                mint: ctx.accounts.stake_mint.to_account_info(),
-            };
-
-            let penalty_cpi_ctx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                penalty_cpi_accounts,
-                signer,
-            );
-
-            transfer_checked(penalty_cpi_ctx, penalty_amount, utils::DECIMALS)?;
-        }
-
-        // Transfer any remaining rewards from reward vault
-        if rewards > 0 {
-            let reward_seeds = &[
-                b"reward_vault",
-                staking_pool.stake_mint.as_ref(),
-                &[ctx.bumps.reward_vault],
-            ];
-            let reward_signer = &[&reward_seeds[..]];
+        transfer_checked(cpi_ctx, amount, utils::DECIMALS)?;
 
-            let transfer_accounts = TransferChecked {
-                from: ctx.accounts.reward_vault.to_account_info(),
-                to: ctx.accounts.user_reward_account.to_account_info(),
-                authority: ctx.accounts.reward_vault.to_account_info(),
-                mint: ctx.accounts.stake_mint.to_account_info(),
+        // The position NFT is redeemed together with the principal
+        if utils::is_position_tokenized(stake_entry) {
+            let position_token_account = ctx
+                .accounts
+                .position_token_account
+                .as_ref()
+                .ok_or(StakingError::InvalidPositionToken)?;
+            let position_mint = ctx
+                .accounts
+                .position_mint
+                .as_ref()
+                .ok_or(StakingError::InvalidPositionToken)?;
+
+            let burn_accounts = Burn {
+                mint: position_mint.to_account_info(),
+                from: position_token_account.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
             };
 
-            let transfer_ctx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                transfer_accounts,
-                reward_signer,
-            );
+            let burn_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), burn_accounts);
 
-            transfer_checked(transfer_ctx, rewards, utils::DECIMALS)?;
+            burn(burn_ctx, 1)?;
         }
 
-        // Update staking pool
         let staking_pool = &mut ctx.accounts.staking_pool;
-        staking_pool.total_staked = staking_pool
-            .total_staked
-            .checked_sub(stake_entry.amount)
-            .ok_or(StakingError::Underflow)?;
-        staking_pool.total_rewards_distributed = staking_pool
-            .total_rewards_distributed
-            .checked_add(rewards)
-            .ok_or(StakingError::Overflow)?;
-
-        // Update user staking account
-        let user_staking_account = &mut ctx.accounts.user_staking_account;
-        user_staking_account.total_staked = user_staking_account
-            .total_staked
-            .checked_sub(stake_entry.amount)
+        staking_pool.total_unbonding = staking_pool
+            .total_unbonding
+            .checked_sub(amount)
             .ok_or(StakingError::Underflow)?;
-        user_staking_account.total_claimed = user_staking_account
-            .total_claimed
-            .checked_add(rewards)
-            .ok_or(StakingError::Overflow)?;
-
-        // Mark stake as inactive
-        stake_entry.is_active = false;
-        stake_entry.total_claimed = stake_entry
-            .total_claimed
-            .checked_add(rewards)
-            .ok_or(StakingError::Overflow)?;
-
-        // Update daily rate
-        utils::update_daily_rate(staking_pool, current_time)?;
+        stake_entry.unbonding_amount = 0;
 
-        emit!(UnstakeEvent {
+        emit!(WithdrawEvent {
             user: ctx.accounts.user.key(),
-            amount: stake_entry.amount,
-            rewards,
+            stake_index: stake_entry.stake_index,
+            amount,
             timestamp: current_time,
         });
 
@@ -351,6 +404,8 @@ pub mod staking_program {
     }
 
     pub fn claim_all(ctx: Context<ClaimAll>) -> Result<()> {
+        utils::validate_not_denied(&ctx.accounts.denylist_entry)?;
+
         let clock = Clock::get()?;
         let current_time = clock.unix_timestamp;
 
@@ -360,41 +415,43 @@ pub mod staking_program {
         // msg!("Claiming all rewards for user: {}", ctx.accounts.user.key());
 
         // Calculate total rewards using the helper function
-        let (total_rewards, valid_stake_indices) = utils::calculate_total_rewards_for_claim_all(
-            &ctx.remaining_accounts,
-            &ctx.accounts.user.key(),
-            staking_pool,
-            &staking_pool.key(),
-            ctx.program_id,
-            current_time,
-        )?;
+        #[allow(clippy::needless_borrow)]
+        let (total_rewards, total_bonus, valid_stake_indices) =
+            utils::calculate_total_rewards_for_claim_all(
+                &ctx.remaining_accounts,
+                &ctx.accounts.user.key(),
+                staking_pool,
+                &staking_pool.key(),
+                ctx.program_id,
+                user_staking_account.boost_bps,
+                current_time,
+            )?;
+        let total_payout = total_rewards
+            .checked_add(total_bonus)
+            .ok_or(StakingError::Overflow)?;
 
         // msg!("Total rewards to claim: {}", total_rewards);
-        require!(total_rewards > 0, StakingError::NoRewardsAvailable);
+        require!(total_payout > 0, StakingError::NoRewardsAvailable);
 
         // Update stake entries that have rewards
-        let current_week = utils::get_week_number(current_time, staking_pool.program_start_time)?;
         let mut stakes_processed = 0u64;
+        let mut total_referral = 0u64;
 
         for index in valid_stake_indices {
             let stake_entry_account_info = &ctx.remaining_accounts[index];
             let mut stake_entry_data = stake_entry_account_info.try_borrow_mut_data()?;
             let mut stake_entry = StakeEntry::try_deserialize(&mut stake_entry_data.as_ref())?;
 
-            // Calculate rewards again for this specific stake
-            let rewards = utils::calculate_claimable_rewards(
-                &stake_entry,
+            // Calculate rewards again for this specific stake and update stake data
+            let (rewards, _) = utils::claim_stake_entry(
+                &mut stake_entry,
                 staking_pool,
+                user_staking_account.boost_bps,
                 current_time,
-                false,
             )?;
-
-            // Update stake data
-            stake_entry.last_claim_time = current_time;
-            stake_entry.last_claim_week = current_week;
-            stake_entry.total_claimed = stake_entry
-                .total_claimed
-                .checked_add(rewards)
+            let referral = utils::accrue_referral_reward(&mut stake_entry, staking_pool, rewards)?;
+            total_referral = total_referral
+                .checked_add(referral)
                 .ok_or(StakingError::Overflow)?;
 
             // Serialize back to account
@@ -424,12 +481,12 @@ pub mod staking_program {
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
 
-        transfer_checked(cpi_ctx, total_rewards, utils::DECIMALS)?;
+        transfer_checked(cpi_ctx, total_payout, utils::DECIMALS)?;
 
         // 10. Update user account and pool
         user_staking_account.total_claimed = user_staking_account
             .total_claimed
-            .checked_add(total_rewards)
+            .checked_add(total_payout)
             .ok_or(StakingError::Overflow)?;
 
         let staking_pool = &mut ctx.accounts.staking_pool;
@@ -437,6 +494,15 @@ pub mod staking_program {
             .total_rewards_distributed
             .checked_add(total_rewards)
             .ok_or(StakingError::Overflow)?;
+        staking_pool.bonus_pot = staking_pool
+            .bonus_pot
+            .checked_sub(total_bonus)
+            .ok_or(StakingError::Underflow)?;
+        staking_pool.total_bonus_distributed = staking_pool
+            .total_bonus_distributed
+            .checked_add(total_bonus)
+            .ok_or(StakingError::Overflow)?;
+        utils::add_referral_pending(staking_pool, total_referral)?;
 
         // 11. Update daily rate
         utils::update_daily_rate(staking_pool, current_time)?;
@@ -444,6 +510,7 @@ pub mod staking_program {
         emit!(ClaimAllEvent {
             user: ctx.accounts.user.key(),
             total_amount: total_rewards,
+            bonus_amount: total_bonus,
             stakes_count: stakes_processed,
             timestamp: current_time,
         });
@@ -456,100 +523,2194 @@ pub mod staking_program {
         let clock = Clock::get()?;
         let current_time = clock.unix_timestamp;
 
-        let (total_rewards, _) = utils::calculate_total_rewards_for_claim_all(
+        #[allow(clippy::needless_borrow)]
+        let (total_rewards, total_bonus, _) = utils::calculate_total_rewards_for_claim_all(
             &ctx.remaining_accounts,
             &ctx.accounts.user.key(),
             &ctx.accounts.staking_pool,
             &ctx.accounts.staking_pool.key(),
             ctx.program_id,
+            ctx.accounts.user_staking_account.boost_bps,
             current_time,
         )?;
 
         // msg!("Total claimable rewards: {}", total_rewards);
 
-        Ok(total_rewards)
+        total_rewards
+            .checked_add(total_bonus)
+            .ok_or(StakingError::Overflow.into())
     }
 
-    pub fn close_program(ctx: Context<CloseProgram>) -> Result<()> {
-        let clock = Clock::get()?;
-        let current_time = clock.unix_timestamp;
+    // View-only: stake limits and the room left under each cap
+    pub fn get_staking_capacity(ctx: Context<GetStakingCapacity>) -> Result<StakingCapacity> {
+        let user_total_staked = ctx
+            .accounts
+            .user_staking_account
+            .as_ref()
+            .map_or(0, |user_staking_account| user_staking_account.total_staked);
 
+        Ok(utils::get_staking_capacity(
+            &ctx.accounts.staking_pool,
+            user_total_staked,
+        ))
+    }
+
+    // View-only: voting power of the user at at_time, summed over the passed stake entries
+    pub fn get_voting_power(
+        ctx: Context<GetVotingPower>,
+        user: Pubkey,
+        at_time: i64,
+    ) -> Result<u64> {
+        let clock = Clock::get()?;
         require!(
-            current_time >= ctx.accounts.staking_pool.program_end_date,
-            StakingError::ProgramNotEnded
+            at_time >= clock.unix_timestamp,
+            StakingError::InvalidVotingPowerTime
         );
 
-        // Transfer remaining tokens to treasury
-        let remaining_balance = ctx.accounts.reward_vault.amount;
+        utils::calculate_voting_power_for_user(
+            ctx.remaining_accounts,
+            &user,
+            &ctx.accounts.staking_pool,
+            &ctx.accounts.staking_pool.key(),
+            ctx.program_id,
+            at_time,
+        )
+    }
 
-        if remaining_balance > 0 {
-            let seeds = &[
-                b"reward_vault",
-                ctx.accounts.staking_pool.stake_mint.as_ref(),
-                &[ctx.bumps.reward_vault],
-            ];
-            let signer = &[&seeds[..]];
+    // View-only: upper bound of the total voting power of the pool at at_time
+    pub fn get_total_voting_power(ctx: Context<GetVotingPower>, at_time: i64) -> Result<u64> {
+        utils::get_total_voting_power(&ctx.accounts.staking_pool, at_time)
+    }
 
-            let cpi_accounts = TransferChecked {
-                from: ctx.accounts.reward_vault.to_account_info(),
-                to: ctx.accounts.treasury_token_account.to_account_info(),
-                authority: ctx.accounts.reward_vault.to_account_info(),
-                mint: ctx.accounts.stake_mint.to_account_info(),
-            };
+    pub fn update_voter_weight_record(ctx: Context<UpdateVoterWeightRecord>) -> Result<()> {
+        let clock = Clock::get()?;
+        let staking_pool = &ctx.accounts.staking_pool;
 
-            let cpi_program = ctx.accounts.token_program.to_account_info();
-            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        let voter_weight = utils::calculate_voting_power_for_user(
+            ctx.remaining_accounts,
+            &ctx.accounts.user.key(),
+            staking_pool,
+            &staking_pool.key(),
+            ctx.program_id,
+            clock.unix_timestamp,
+        )?;
 
-            transfer_checked(cpi_ctx, remaining_balance, utils::DECIMALS)?;
-        }
+        // The weight decays every second, so it is valid for the current slot only
+        let voter_weight_record = &mut ctx.accounts.voter_weight_record;
+        voter_weight_record.realm = staking_pool.governance_realm;
+        voter_weight_record.governing_token_mint = staking_pool.governing_token_mint;
+        voter_weight_record.governing_token_owner = ctx.accounts.user.key();
+        voter_weight_record.voter_weight = voter_weight;
+        voter_weight_record.voter_weight_expiry = Some(clock.slot);
+        voter_weight_record.weight_action = None;
+        voter_weight_record.weight_action_target = None;
 
         Ok(())
     }
 
-    pub fn update_normalization_k(ctx: Context<UpdateNormalizationK>, new_k: u128) -> Result<()> {
-        require!(
-            ctx.accounts.authority.key() == ctx.accounts.staking_pool.authority,
-            StakingError::Unauthorized
-        );
-
-        require!(new_k > 0, StakingError::InvalidNormalizationK);
-
+    pub fn update_max_voter_weight_record(ctx: Context<UpdateMaxVoterWeightRecord>) -> Result<()> {
+        let clock = Clock::get()?;
         let staking_pool = &mut ctx.accounts.staking_pool;
-        let old_k = staking_pool.normalization_k;
-        staking_pool.normalization_k = new_k;
 
-        // Update daily rate with new normalization_k
-        let clock = Clock::get()?;
-        utils::update_daily_rate(staking_pool, clock.unix_timestamp)?;
+        utils::checkpoint_voting_power(staking_pool, clock.unix_timestamp)?;
+        let max_voter_weight = utils::get_total_voting_power(staking_pool, clock.unix_timestamp)?;
 
-        emit!(NormalizationKUpdatedEvent {
-            old_k,
-            new_k,
-            timestamp: clock.unix_timestamp,
-        });
+        let max_voter_weight_record = &mut ctx.accounts.max_voter_weight_record;
+        max_voter_weight_record.realm = staking_pool.governance_realm;
+        max_voter_weight_record.governing_token_mint = staking_pool.governing_token_mint;
+        max_voter_weight_record.max_voter_weight = max_voter_weight;
+        max_voter_weight_record.max_voter_weight_expiry = Some(clock.slot);
 
         Ok(())
     }
 
-    pub fn update_daily_rate_at_index(
-        ctx: Context<UpdateDailyRate>,
-        day_index: u64,
-        new_rate: u64,
-    ) -> Result<()> {
-        require!(
-            day_index < utils::MAX_DAILY_RATES as u64,
-            StakingError::DayIndexOutOfBounds
+    pub fn mint_position_nft(ctx: Context<MintPositionNft>, stake_index: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let stake_entry = &mut ctx.accounts.stake_entry;
+        require!(stake_entry.is_active, StakingError::StakeNotActive);
+
+        // Rewards earned with the owner's boost stay with the position
+        utils::settle_rewards(
+            stake_entry,
+            &ctx.accounts.staking_pool,
+            ctx.accounts.user_staking_account.boost_bps,
+            current_time,
+        )?;
+
+        let position_mint_key = ctx.accounts.position_mint.key();
+        let staking_pool_key = ctx.accounts.staking_pool.key();
+        let token_metadata = TokenMetadata {
+            update_authority: OptionalNonZeroPubkey::try_from(Some(staking_pool_key))?,
+            mint: position_mint_key,
+            name: format!("{} #{}", utils::POSITION_NAME, stake_index),
+            symbol: utils::POSITION_SYMBOL.to_string(),
+            uri: String::new(),
+            additional_metadata: utils::get_position_metadata(
+                stake_entry,
+                ctx.accounts.staking_pool.month_convention,
+            ),
+        };
+
+        // Pay rent for the metadata the token program appends to the mint
+        let mint_info = ctx.accounts.position_mint.to_account_info();
+        let required_lamports = Rent::get()?
+            .minimum_balance(mint_info.data_len() + token_metadata.tlv_size_of()?)
+            .saturating_sub(mint_info.lamports());
+        if required_lamports > 0 {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.user.to_account_info(),
+                to: mint_info.clone(),
+            };
+            let cpi_ctx =
+                CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+
+            system_program::transfer(cpi_ctx, required_lamports)?;
+        }
+
+        let seeds = &[
+            b"staking_pool",
+            ctx.accounts.staking_pool.stake_mint.as_ref(),
+            &[ctx.bumps.staking_pool],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TokenMetadataInitialize {
+            program_id: ctx.accounts.token_program.to_account_info(),
+            metadata: mint_info.clone(),
+            update_authority: ctx.accounts.staking_pool.to_account_info(),
+            mint_authority: ctx.accounts.staking_pool.to_account_info(),
+            mint: mint_info.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer,
         );
 
-        let staking_pool = &mut ctx.accounts.staking_pool;
-        
-        // Ensure the daily_rates vector is large enough
-        if staking_pool.daily_rates.len() <= day_index as usize {
-            staking_pool.daily_rates.resize(day_index as usize + 1, 0);
+        token_metadata_initialize(
+            cpi_ctx,
+            token_metadata.name.clone(),
+            token_metadata.symbol.clone(),
+            token_metadata.uri.clone(),
+        )?;
+
+        for (key, value) in token_metadata.additional_metadata {
+            let cpi_accounts = TokenMetadataUpdateField {
+                program_id: ctx.accounts.token_program.to_account_info(),
+                metadata: mint_info.clone(),
+                update_authority: ctx.accounts.staking_pool.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                cpi_accounts,
+                signer,
+            );
+
+            token_metadata_update_field(cpi_ctx, Field::Key(key), value)?;
         }
 
-        staking_pool.daily_rates[day_index as usize] = new_rate;
+        let cpi_accounts = MintTo {
+            mint: mint_info.clone(),
+            to: ctx.accounts.position_token_account.to_account_info(),
+            authority: ctx.accounts.staking_pool.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer,
+        );
+
+        mint_to(cpi_ctx, 1)?;
+
+        // Fix the supply at one
+        let cpi_accounts = SetAuthority {
+            current_authority: ctx.accounts.staking_pool.to_account_info(),
+            account_or_mint: mint_info,
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer,
+        );
+
+        set_authority(cpi_ctx, AuthorityType::MintTokens, None)?;
+
+        let stake_entry = &mut ctx.accounts.stake_entry;
+        stake_entry.position_mint = position_mint_key;
+
+        emit!(PositionMintedEvent {
+            owner: ctx.accounts.user.key(),
+            stake_index,
+            position_mint: position_mint_key,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn claim_position(ctx: Context<ClaimPosition>, _stake_index: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let stake_entry = &mut ctx.accounts.stake_entry;
+        require!(stake_entry.is_active, StakingError::StakeNotActive);
+        require!(
+            utils::is_position_tokenized(stake_entry),
+            StakingError::InvalidPositionToken
+        );
+        utils::validate_not_denied(&ctx.accounts.denylist_entry)?;
+
+        let staking_pool = &ctx.accounts.staking_pool;
+        let (rewards, bonus) =
+            utils::claim_stake_entry(stake_entry, staking_pool, 0, current_time)?;
+        let payout = rewards.checked_add(bonus).ok_or(StakingError::Overflow)?;
+        require!(payout > 0, StakingError::NoRewardsAvailable);
+        let referral = utils::accrue_referral_reward(stake_entry, staking_pool, rewards)?;
+
+        let seeds = &[
+            b"reward_vault",
+            staking_pool.stake_mint.as_ref(),
+            &[ctx.bumps.reward_vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.reward_vault.to_account_info(),
+            to: ctx.accounts.holder_reward_account.to_account_info(),
+            authority: ctx.accounts.reward_vault.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        transfer_checked(cpi_ctx, payout, utils::DECIMALS)?;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        staking_pool.total_rewards_distributed = staking_pool
+            .total_rewards_distributed
+            .checked_add(rewards)
+            .ok_or(StakingError::Overflow)?;
+        staking_pool.bonus_pot = staking_pool
+            .bonus_pot
+            .checked_sub(bonus)
+            .ok_or(StakingError::Underflow)?;
+        staking_pool.total_bonus_distributed = staking_pool
+            .total_bonus_distributed
+            .checked_add(bonus)
+            .ok_or(StakingError::Overflow)?;
+        utils::add_referral_pending(staking_pool, referral)?;
+
+        utils::update_daily_rate(staking_pool, current_time)?;
+
+        emit!(PositionClaimEvent {
+            holder: ctx.accounts.holder.key(),
+            position_mint: stake_entry.position_mint,
+            amount: rewards,
+            bonus_amount: bonus,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+        let referrer_account = &mut ctx.accounts.referrer_account;
+        referrer_account.staking_pool = ctx.accounts.staking_pool.key();
+        referrer_account.referrer = ctx.accounts.referrer.key();
+        referrer_account.referral_count = 0;
+        referrer_account.total_referred = 0;
+        referrer_account.total_claimed = 0;
+
+        emit!(ReferrerRegisteredEvent {
+            referrer: ctx.accounts.referrer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+        utils::validate_not_denied(&ctx.accounts.denylist_entry)?;
+
+        let current_time = Clock::get()?.unix_timestamp;
+        let referrer = ctx.accounts.referrer.key();
+        let staking_pool_key = ctx.accounts.staking_pool.key();
+
+        // Collect the share accrued on every passed stake entry referred by the signer
+        let mut total_amount = 0u64;
+        let mut stakes_processed = 0u64;
+
+        for stake_entry_account_info in ctx.remaining_accounts.iter() {
+            let mut stake_entry_data = stake_entry_account_info.try_borrow_mut_data()?;
+            let mut stake_entry = StakeEntry::try_deserialize(&mut stake_entry_data.as_ref())?;
+
+            let (expected_pda, _bump) = Pubkey::find_program_address(
+                &[
+                    b"stake_entry",
+                    stake_entry.owner.as_ref(),
+                    staking_pool_key.as_ref(),
+                    &stake_entry.stake_index.to_le_bytes(),
+                ],
+                ctx.program_id,
+            );
+            require!(
+                stake_entry_account_info.key() == expected_pda,
+                StakingError::InvalidStakeIndex
+            );
+            require!(stake_entry.referrer == referrer, StakingError::InvalidReferrer);
+
+            if stake_entry.referral_accrued == 0 {
+                continue;
+            }
+
+            total_amount = total_amount
+                .checked_add(stake_entry.referral_accrued)
+                .ok_or(StakingError::Overflow)?;
+            stake_entry.referral_accrued = 0;
+
+            let mut writer = stake_entry_data.as_mut();
+            stake_entry.try_serialize(&mut writer)?;
+
+            stakes_processed = stakes_processed
+                .checked_add(1)
+                .ok_or(StakingError::Overflow)?;
+        }
+
+        require!(total_amount > 0, StakingError::NoRewardsAvailable);
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let seeds = &[
+            b"reward_vault",
+            staking_pool.stake_mint.as_ref(),
+            &[ctx.bumps.reward_vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.reward_vault.to_account_info(),
+            to: ctx.accounts.referrer_reward_account.to_account_info(),
+            authority: ctx.accounts.reward_vault.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        transfer_checked(cpi_ctx, total_amount, utils::DECIMALS)?;
+
+        staking_pool.referral_pending = staking_pool
+            .referral_pending
+            .checked_sub(total_amount)
+            .ok_or(StakingError::Underflow)?;
+
+        let referrer_account = &mut ctx.accounts.referrer_account;
+        referrer_account.total_claimed = referrer_account
+            .total_claimed
+            .checked_add(total_amount)
+            .ok_or(StakingError::Overflow)?;
+
+        emit!(ReferralRewardsClaimedEvent {
+            referrer,
+            amount: total_amount,
+            stakes_count: stakes_processed,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn initialize_liquid_mint(ctx: Context<InitializeLiquidMint>) -> Result<()> {
+        let clock = Clock::get()?;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        staking_pool.liquid_mint = ctx.accounts.liquid_mint.key();
+        staking_pool.liquid_staked = 0;
+        staking_pool.liquid_exchange_rate = utils::EXCHANGE_RATE_PRECISION;
+        staking_pool.liquid_compounded_day =
+            utils::get_day_index(clock.unix_timestamp, staking_pool.program_start_time)?;
+
+        Ok(())
+    }
+
+    pub fn compound_liquid(ctx: Context<CompoundLiquid>) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let accounts = ctx.accounts;
+        compound_liquid_rewards(
+            &mut accounts.staking_pool,
+            &accounts.reward_vault,
+            ctx.bumps.reward_vault,
+            &accounts.stake_vault,
+            &accounts.stake_mint,
+            &accounts.token_program,
+            accounts.liquid_mint.supply,
+            current_time,
+        )?;
+
+        utils::update_daily_rate(&mut accounts.staking_pool, current_time)?;
+
+        Ok(())
+    }
+
+    pub fn stake_liquid(
+        ctx: Context<StakeLiquid>,
+        amount: u64,
+        allowlist_proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        require!(amount > 0, StakingError::InvalidAmount);
+        utils::validate_stake_access(
+            &ctx.accounts.staking_pool,
+            &ctx.accounts.user.key(),
+            ctx.accounts.allowlist_entry.as_deref(),
+            &ctx.accounts.denylist_entry,
+            &allowlist_proof,
+        )?;
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        // Compound first so new receipts are priced at the current exchange rate
+        let accounts = ctx.accounts;
+        compound_liquid_rewards(
+            &mut accounts.staking_pool,
+            &accounts.reward_vault,
+            ctx.bumps.reward_vault,
+            &accounts.stake_vault,
+            &accounts.stake_mint,
+            &accounts.token_program,
+            accounts.liquid_mint.supply,
+            current_time,
+        )?;
+
+        // The per user cap covers the user's locked stake and liquid receipts together
+        let user_liquid_value = utils::get_liquid_value(
+            accounts.user_liquid_account.amount,
+            accounts.staking_pool.liquid_exchange_rate,
+        )?;
+        let user_total_staked = utils::get_user_total_staked(&accounts.user_staking_account)?
+            .checked_add(user_liquid_value)
+            .ok_or(StakingError::Overflow)?;
+        utils::validate_stake_caps(
+            &accounts.staking_pool,
+            user_total_staked,
+            utils::LIQUID_DURATION_MONTHS,
+            amount,
+        )?;
+
+        let available_rewards = utils::get_available_rewards(&accounts.staking_pool, current_time)?;
+        require!(available_rewards > 0, StakingError::RewardPoolExhausted);
+
+        let cpi_accounts = TransferChecked {
+            from: accounts.user_token_account.to_account_info(),
+            to: accounts.stake_vault.to_account_info(),
+            authority: accounts.user.to_account_info(),
+            mint: accounts.stake_mint.to_account_info(),
+        };
+
+        let cpi_program = accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        let before = accounts.stake_vault.amount;
+        transfer_checked(cpi_ctx, amount, utils::DECIMALS)?;
+        accounts.stake_vault.reload()?;
+        let delta = accounts.stake_vault.amount.saturating_sub(before);
+
+        let receipt_amount = (delta as u128)
+            .checked_mul(utils::EXCHANGE_RATE_PRECISION)
+            .ok_or(StakingError::Overflow)?
+            .checked_div(accounts.staking_pool.liquid_exchange_rate)
+            .ok_or(StakingError::DivisionByZero)? as u64;
+        require!(receipt_amount > 0, StakingError::InvalidAmount);
+
+        let seeds = &[
+            b"staking_pool",
+            accounts.staking_pool.stake_mint.as_ref(),
+            &[ctx.bumps.staking_pool],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = MintTo {
+            mint: accounts.liquid_mint.to_account_info(),
+            to: accounts.user_liquid_account.to_account_info(),
+            authority: accounts.staking_pool.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer,
+        );
+
+        mint_to(cpi_ctx, receipt_amount)?;
+        accounts.liquid_mint.reload()?;
+
+        let staking_pool = &mut accounts.staking_pool;
+        staking_pool.liquid_staked = staking_pool
+            .liquid_staked
+            .checked_add(delta)
+            .ok_or(StakingError::Overflow)?;
+        staking_pool.total_staked = staking_pool
+            .total_staked
+            .checked_add(delta)
+            .ok_or(StakingError::Overflow)?;
+        staking_pool.liquid_exchange_rate = utils::get_liquid_exchange_rate(
+            staking_pool.liquid_staked,
+            accounts.liquid_mint.supply,
+        )?;
+
+        utils::update_daily_rate(staking_pool, current_time)?;
+
+        emit!(LiquidStakeEvent {
+            user: accounts.user.key(),
+            amount: delta,
+            receipt_amount,
+            exchange_rate: staking_pool.liquid_exchange_rate,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn redeem_liquid(ctx: Context<RedeemLiquid>, receipt_amount: u64) -> Result<()> {
+        require!(
+            ctx.accounts.staking_pool.unbonding_cooldown == 0,
+            StakingError::UnbondingRequired
+        );
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let accounts = ctx.accounts;
+        let amount = burn_liquid_receipts(
+            &mut accounts.staking_pool,
+            &mut accounts.liquid_mint,
+            &accounts.user_liquid_account,
+            &accounts.user,
+            &accounts.reward_vault,
+            ctx.bumps.reward_vault,
+            &accounts.stake_vault,
+            &accounts.stake_mint,
+            &accounts.token_program,
+            receipt_amount,
+            current_time,
+        )?;
+
+        let seeds = &[
+            b"stake_vault",
+            accounts.staking_pool.stake_mint.as_ref(),
+            &[ctx.bumps.stake_vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: accounts.stake_vault.to_account_info(),
+            to: accounts.user_token_account.to_account_info(),
+            authority: accounts.stake_vault.to_account_info(),
+            mint: accounts.stake_mint.to_account_info(),
+        };
+
+        let cpi_program = accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        transfer_checked(cpi_ctx, amount, utils::DECIMALS)?;
+
+        let staking_pool = &mut accounts.staking_pool;
+        utils::update_daily_rate(staking_pool, current_time)?;
+
+        emit!(LiquidRedeemEvent {
+            user: accounts.user.key(),
+            amount,
+            receipt_amount,
+            exchange_rate: staking_pool.liquid_exchange_rate,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn request_redeem_liquid(ctx: Context<RequestRedeemLiquid>, receipt_amount: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let accounts = ctx.accounts;
+        let amount = burn_liquid_receipts(
+            &mut accounts.staking_pool,
+            &mut accounts.liquid_mint,
+            &accounts.user_liquid_account,
+            &accounts.user,
+            &accounts.reward_vault,
+            ctx.bumps.reward_vault,
+            &accounts.stake_vault,
+            &accounts.stake_mint,
+            &accounts.token_program,
+            receipt_amount,
+            current_time,
+        )?;
+
+        let staking_pool = &mut accounts.staking_pool;
+        let unlock_time = current_time
+            .checked_add(staking_pool.unbonding_cooldown)
+            .ok_or(StakingError::Overflow)?;
+        staking_pool.total_unbonding = staking_pool
+            .total_unbonding
+            .checked_add(amount)
+            .ok_or(StakingError::Overflow)?;
+
+        let liquid_unbonding = &mut accounts.liquid_unbonding;
+        liquid_unbonding.staking_pool = staking_pool.key();
+        liquid_unbonding.owner = accounts.user.key();
+        liquid_unbonding.amount = liquid_unbonding
+            .amount
+            .checked_add(amount)
+            .ok_or(StakingError::Overflow)?;
+        liquid_unbonding.unlock_time = unlock_time;
+
+        utils::update_daily_rate(staking_pool, current_time)?;
+
+        emit!(LiquidRedeemRequestedEvent {
+            user: accounts.user.key(),
+            amount,
+            receipt_amount,
+            exchange_rate: staking_pool.liquid_exchange_rate,
+            unlock_time,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn withdraw_liquid(ctx: Context<WithdrawLiquid>) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let liquid_unbonding = &ctx.accounts.liquid_unbonding;
+        let amount = liquid_unbonding.amount;
+        require!(amount > 0, StakingError::NoPendingUnbonding);
+        require!(
+            current_time >= liquid_unbonding.unlock_time,
+            StakingError::UnbondingNotFinished
+        );
+
+        let staking_pool = &ctx.accounts.staking_pool;
+        let seeds = &[
+            b"stake_vault",
+            staking_pool.stake_mint.as_ref(),
+            &[ctx.bumps.stake_vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.stake_vault.to_account_info(),
+            to: ctx.accounts.user_token_account.to_account_info(),
+            authority: ctx.accounts.stake_vault.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        transfer_checked(cpi_ctx, amount, utils::DECIMALS)?;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        staking_pool.total_unbonding = staking_pool
+            .total_unbonding
+            .checked_sub(amount)
+            .ok_or(StakingError::Underflow)?;
+
+        emit!(LiquidWithdrawEvent {
+            user: ctx.accounts.user.key(),
+            amount,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    // View-only: stake tokens per receipt token, scaled by 10^12
+    pub fn get_liquid_exchange_rate(ctx: Context<GetLiquidExchangeRate>) -> Result<u128> {
+        Ok(ctx.accounts.staking_pool.liquid_exchange_rate)
+    }
+
+    pub fn fund_reward_vault(ctx: Context<FundRewardVault>, amount: u64) -> Result<()> {
+        require!(amount > 0, StakingError::InvalidAmount);
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.funder_token_account.to_account_info(),
+            to: ctx.accounts.reward_vault.to_account_info(),
+            authority: ctx.accounts.funder.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        // Record what actually arrived in the vault (transfer fee extensions)
+        let before = ctx.accounts.reward_vault.amount;
+        transfer_checked(cpi_ctx, amount, utils::DECIMALS)?;
+        ctx.accounts.reward_vault.reload()?;
+        let after = ctx.accounts.reward_vault.amount;
+        let delta = after.saturating_sub(before);
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        staking_pool.total_funded = staking_pool
+            .total_funded
+            .checked_add(delta)
+            .ok_or(StakingError::Overflow)?;
+
+        // Available rewards changed, refresh today's rate
+        utils::update_daily_rate(staking_pool, current_time)?;
+
+        emit!(RewardFundedEvent {
+            funder: ctx.accounts.funder.key(),
+            amount: delta,
+            total_funded: staking_pool.total_funded,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    // Tips for crank_daily_rate, held in the reward vault apart from the rewards
+    pub fn fund_crank_budget(ctx: Context<FundRewardVault>, amount: u64) -> Result<()> {
+        require!(amount > 0, StakingError::InvalidAmount);
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.funder_token_account.to_account_info(),
+            to: ctx.accounts.reward_vault.to_account_info(),
+            authority: ctx.accounts.funder.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        let before = ctx.accounts.reward_vault.amount;
+        transfer_checked(cpi_ctx, amount, utils::DECIMALS)?;
+        ctx.accounts.reward_vault.reload()?;
+        let delta = ctx.accounts.reward_vault.amount.saturating_sub(before);
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        staking_pool.crank_budget = staking_pool
+            .crank_budget
+            .checked_add(delta)
+            .ok_or(StakingError::Overflow)?;
+
+        emit!(CrankBudgetFundedEvent {
+            funder: ctx.accounts.funder.key(),
+            amount: delta,
+            crank_budget: staking_pool.crank_budget,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless: finalizes past days, backfills days without a rate update and
+    // refreshes today's rate. Calls that finalize days get crank_tip if a token account is passed
+    pub fn crank_daily_rate(ctx: Context<CrankDailyRate>) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let last_computed_day = staking_pool.rate_snapshot.day_index;
+        utils::update_daily_rate(staking_pool, current_time)?;
+
+        let tip = if ctx.accounts.cranker_token_account.is_some() {
+            utils::get_crank_tip(staking_pool, last_computed_day)
+        } else {
+            0
+        };
+
+        if let Some(cranker_token_account) = ctx
+            .accounts
+            .cranker_token_account
+            .as_ref()
+            .filter(|_| tip > 0)
+        {
+            let seeds = &[
+                b"reward_vault",
+                staking_pool.stake_mint.as_ref(),
+                &[ctx.bumps.reward_vault],
+            ];
+            let signer = &[&seeds[..]];
+
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.reward_vault.to_account_info(),
+                to: cranker_token_account.to_account_info(),
+                authority: ctx.accounts.reward_vault.to_account_info(),
+                mint: ctx.accounts.stake_mint.to_account_info(),
+            };
+
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+            transfer_checked(cpi_ctx, tip, utils::DECIMALS)?;
+
+            staking_pool.crank_budget = staking_pool
+                .crank_budget
+                .checked_sub(tip)
+                .ok_or(StakingError::Underflow)?;
+        }
+
+        emit!(DailyRateCrankedEvent {
+            cranker: ctx.accounts.cranker.key(),
+            day_index: staking_pool.rate_snapshot.day_index,
+            tip,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn update_allowlist(
+        ctx: Context<UpdateAllowlist>,
+        enabled: bool,
+        merkle_root: [u8; 32],
+        gatekeeper: Pubkey,
+    ) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        staking_pool.allowlist_enabled = enabled;
+        staking_pool.allowlist_root = merkle_root;
+        staking_pool.gatekeeper = gatekeeper;
+
+        emit!(AllowlistUpdatedEvent {
+            enabled,
+            merkle_root,
+            gatekeeper,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn add_allowlist_entry(ctx: Context<AddAllowlistEntry>, wallet: Pubkey) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+
+        let allowlist_entry = &mut ctx.accounts.allowlist_entry;
+        allowlist_entry.staking_pool = ctx.accounts.staking_pool.key();
+        allowlist_entry.wallet = wallet;
+        allowlist_entry.added_by = ctx.accounts.gatekeeper.key();
+        allowlist_entry.added_at = current_time;
+
+        emit!(AllowlistEntryUpdatedEvent {
+            wallet,
+            added: true,
+            gatekeeper: ctx.accounts.gatekeeper.key(),
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_allowlist_entry(ctx: Context<RemoveAllowlistEntry>, wallet: Pubkey) -> Result<()> {
+        emit!(AllowlistEntryUpdatedEvent {
+            wallet,
+            added: false,
+            gatekeeper: ctx.accounts.gatekeeper.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn add_denylist_entry(ctx: Context<AddDenylistEntry>, wallet: Pubkey) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+
+        let denylist_entry = &mut ctx.accounts.denylist_entry;
+        denylist_entry.staking_pool = ctx.accounts.staking_pool.key();
+        denylist_entry.wallet = wallet;
+        denylist_entry.added_by = ctx.accounts.gatekeeper.key();
+        denylist_entry.added_at = current_time;
+
+        emit!(DenylistEntryUpdatedEvent {
+            wallet,
+            added: true,
+            gatekeeper: ctx.accounts.gatekeeper.key(),
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_denylist_entry(ctx: Context<RemoveDenylistEntry>, wallet: Pubkey) -> Result<()> {
+        emit!(DenylistEntryUpdatedEvent {
+            wallet,
+            added: false,
+            gatekeeper: ctx.accounts.gatekeeper.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn close_program(ctx: Context<CloseProgram>) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        require!(
+            current_time >= ctx.accounts.staking_pool.program_end_date,
+            StakingError::ProgramNotEnded
+        );
+
+        // Transfer remaining tokens to treasury, rewards still owed to stakers stay in the vault
+        let remaining_balance = utils::get_excess_rewards(
+            &ctx.accounts.staking_pool,
+            ctx.accounts.reward_vault.amount,
+            current_time,
+        )?;
+
+        if remaining_balance > 0 {
+            let seeds = &[
+                b"reward_vault",
+                ctx.accounts.staking_pool.stake_mint.as_ref(),
+                &[ctx.bumps.reward_vault],
+            ];
+            let signer = &[&seeds[..]];
+
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.reward_vault.to_account_info(),
+                to: ctx.accounts.treasury_token_account.to_account_info(),
+                authority: ctx.accounts.reward_vault.to_account_info(),
+                mint: ctx.accounts.stake_mint.to_account_info(),
+            };
+
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+            transfer_checked(cpi_ctx, remaining_balance, utils::DECIMALS)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn queue_excess_withdrawal(ctx: Context<QueueExcessWithdrawal>, amount: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        require!(amount > 0, StakingError::InvalidAmount);
+        require!(
+            staking_pool.pending_withdrawal_amount == 0,
+            StakingError::WithdrawalAlreadyQueued
+        );
+
+        let excess = utils::get_excess_rewards(
+            staking_pool,
+            ctx.accounts.reward_vault.amount,
+            current_time,
+        )?;
+        require!(amount <= excess, StakingError::ExceedsExcessRewards);
+
+        let eta = current_time
+            .checked_add(staking_pool.timelock_delay)
+            .ok_or(StakingError::Overflow)?;
+        staking_pool.pending_withdrawal_amount = amount;
+        staking_pool.pending_withdrawal_eta = eta;
+
+        emit!(ExcessWithdrawalQueuedEvent {
+            amount,
+            eta,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_excess_withdrawal(ctx: Context<CancelExcessWithdrawal>) -> Result<()> {
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        let amount = staking_pool.pending_withdrawal_amount;
+        require!(amount > 0, StakingError::NoPendingWithdrawal);
+
+        staking_pool.pending_withdrawal_amount = 0;
+        staking_pool.pending_withdrawal_eta = 0;
+
+        emit!(ExcessWithdrawalCancelledEvent {
+            amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn withdraw_excess_rewards(ctx: Context<WithdrawExcessRewards>, amount: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let staking_pool = &ctx.accounts.staking_pool;
+        require!(
+            staking_pool.pending_withdrawal_amount > 0,
+            StakingError::NoPendingWithdrawal
+        );
+        require!(
+            amount == staking_pool.pending_withdrawal_amount,
+            StakingError::InvalidAmount
+        );
+        require!(
+            current_time >= staking_pool.pending_withdrawal_eta,
+            StakingError::TimelockNotExpired
+        );
+
+        // Liabilities may have grown while the withdrawal was queued
+        let excess = utils::get_excess_rewards(
+            staking_pool,
+            ctx.accounts.reward_vault.amount,
+            current_time,
+        )?;
+        require!(amount <= excess, StakingError::ExceedsExcessRewards);
+
+        let seeds = &[
+            b"reward_vault",
+            staking_pool.stake_mint.as_ref(),
+            &[ctx.bumps.reward_vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.reward_vault.to_account_info(),
+            to: ctx.accounts.treasury_token_account.to_account_info(),
+            authority: ctx.accounts.reward_vault.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        transfer_checked(cpi_ctx, amount, utils::DECIMALS)?;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        staking_pool.pending_withdrawal_amount = 0;
+        staking_pool.pending_withdrawal_eta = 0;
+        staking_pool.total_funded = staking_pool.total_funded.saturating_sub(amount);
+
+        emit!(ExcessWithdrawalExecutedEvent {
+            amount,
+            total_funded: staking_pool.total_funded,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn queue_parameter_change(
+        ctx: Context<QueueParameterChange>,
+        change: ParameterChange,
+    ) -> Result<()> {
+        utils::validate_parameter_change(&change)?;
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let staking_pool = &mut ctx.accounts.staking_pool;
+        if let ParameterChange::DailyRate { day_index, .. } = change {
+            utils::validate_daily_rate_override(staking_pool, day_index, current_time)?;
+        }
+        let change_id = staking_pool.change_count;
+        let eta = current_time
+            .checked_add(staking_pool.timelock_delay)
+            .ok_or(StakingError::Overflow)?;
+
+        let pending_change = &mut ctx.accounts.pending_change;
+        pending_change.staking_pool = staking_pool.key();
+        pending_change.change_id = change_id;
+        pending_change.change = change.clone();
+        pending_change.queued_at = current_time;
+        pending_change.eta = eta;
+
+        staking_pool.change_count = staking_pool
+            .change_count
+            .checked_add(1)
+            .ok_or(StakingError::Overflow)?;
+
+        emit!(ParameterChangeQueuedEvent {
+            change_id,
+            change,
+            eta,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_parameter_change(
+        ctx: Context<CancelParameterChange>,
+        change_id: u64,
+    ) -> Result<()> {
+        emit!(ParameterChangeCancelledEvent {
+            change_id,
+            change: ctx.accounts.pending_change.change.clone(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn execute_parameter_change(
+        ctx: Context<ExecuteParameterChange>,
+        change_id: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let pending_change = &ctx.accounts.pending_change;
+        require!(
+            current_time >= pending_change.eta,
+            StakingError::TimelockNotExpired
+        );
+
+        let change = pending_change.change.clone();
+        let staking_pool = &mut ctx.accounts.staking_pool;
+
+        match change {
+            ParameterChange::NormalizationK { new_k } => {
+                utils::record_weight_change(staking_pool, current_time)?;
+                let old_k = staking_pool.normalization_k;
+                staking_pool.normalization_k = new_k;
+
+                emit!(NormalizationKUpdatedEvent {
+                    old_k,
+                    new_k,
+                    timestamp: current_time,
+                });
+            }
+            ParameterChange::TierWeights { weights } => {
+                utils::record_weight_change(staking_pool, current_time)?;
+                staking_pool.tier_weights = weights;
+            }
+            ParameterChange::FlexibleWeight { weight } => {
+                utils::record_weight_change(staking_pool, current_time)?;
+                staking_pool.flexible_weight = weight;
+            }
+            ParameterChange::AccrualMode { mode } => {
+                staking_pool.accrual_mode = mode;
+            }
+            ParameterChange::StakingCaps { caps } => {
+                staking_pool.staking_caps = caps;
+            }
+            ParameterChange::MaturedWeight { weight } => {
+                // Matured stakes never earn more than the shortest lock
+                require!(
+                    weight <= staking_pool.tier_weights[0],
+                    StakingError::InvalidTierWeights
+                );
+                utils::record_weight_change(staking_pool, current_time)?;
+                staking_pool.matured_weight = weight;
+            }
+            ParameterChange::MaxPenalty { percent } => {
+                staking_pool.max_penalty_percent = percent;
+            }
+            ParameterChange::Treasury { treasury } => {
+                utils::validate_fee_destination(
+                    ctx.accounts.treasury_token_account.as_ref(),
+                    &treasury,
+                    &ctx.accounts.stake_mint,
+                )
+                .map_err(|_| StakingError::InvalidTreasuryAccount)?;
+
+                staking_pool.treasury_address = treasury;
+            }
+            ParameterChange::EmissionSchedule { schedule } => {
+                staking_pool.emission_schedule = schedule;
+            }
+            ParameterChange::DailyRate { day_index, rate } => {
+                // The day may have started while the change was queued
+                utils::validate_daily_rate_override(staking_pool, day_index, current_time)?;
+
+                let old_rate = utils::get_daily_rate(staking_pool, day_index);
+                utils::set_daily_rate_override(staking_pool, day_index, rate)?;
+
+                emit!(DailyRateOverriddenEvent {
+                    day_index,
+                    computed_rate: utils::get_computed_daily_rate(staking_pool, day_index),
+                    old_rate,
+                    new_rate: rate,
+                    timestamp: current_time,
+                });
+            }
+            ParameterChange::TimelockDelay { delay } => {
+                staking_pool.timelock_delay = delay;
+            }
+            ParameterChange::FeeRouting { fee_split, buyback } => {
+                if buyback != Pubkey::default() {
+                    utils::validate_fee_destination(
+                        ctx.accounts.buyback_token_account.as_ref(),
+                        &buyback,
+                        &ctx.accounts.stake_mint,
+                    )?;
+                }
+
+                staking_pool.fee_split = fee_split;
+                staking_pool.buyback_address = buyback;
+            }
+            ParameterChange::PenaltyMode { mode } => {
+                staking_pool.penalty_mode = mode;
+            }
+            ParameterChange::BoostConfig { config } => {
+                staking_pool.boost_config = config;
+            }
+            ParameterChange::Governance {
+                realm,
+                governing_token_mint,
+            } => {
+                staking_pool.governance_realm = realm;
+                staking_pool.governing_token_mint = governing_token_mint;
+            }
+            ParameterChange::UnbondingCooldown { cooldown } => {
+                staking_pool.unbonding_cooldown = cooldown;
+            }
+            ParameterChange::ReferralBps { bps } => {
+                staking_pool.referral_bps = bps;
+            }
+            ParameterChange::GapFillMode { mode } => {
+                staking_pool.gap_fill_mode = mode;
+            }
+            ParameterChange::CrankTip { tip } => {
+                staking_pool.crank_tip = tip;
+            }
+            ParameterChange::RateSmoothing { config } => {
+                staking_pool.rate_smoothing = config;
+            }
+            ParameterChange::RewardFunder { funder } => {
+                staking_pool.reward_funder = funder;
+            }
+        }
+
+        // Rate inputs may have changed, refresh today's rate
+        if !matches!(change, ParameterChange::DailyRate { .. }) {
+            utils::update_daily_rate(staking_pool, current_time)?;
+        }
+
+        emit!(ParameterChangeExecutedEvent {
+            change_id,
+            change,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn attach_boost_nft(ctx: Context<AttachBoostNft>) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let staking_pool = &ctx.accounts.staking_pool;
+        require!(
+            ctx.accounts.boost_account.nft_mint == Pubkey::default(),
+            StakingError::BoostNftAlreadyAttached
+        );
+        utils::validate_boost_nft(&ctx.accounts.nft_mint, &staking_pool.boost_config)?;
+
+        // Rewards up to today are earned with the old boost
+        utils::settle_all_user_rewards(
+            ctx.remaining_accounts,
+            &ctx.accounts.user_staking_account,
+            staking_pool,
+            &staking_pool.key(),
+            ctx.program_id,
+            current_time,
+        )?;
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.user_nft_account.to_account_info(),
+            to: ctx.accounts.nft_vault.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+            mint: ctx.accounts.nft_mint.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        transfer_checked(cpi_ctx, 1, 0)?;
+
+        let boost_account = &mut ctx.accounts.boost_account;
+        boost_account.owner = ctx.accounts.user.key();
+        boost_account.nft_mint = ctx.accounts.nft_mint.key();
+
+        let user_staking_account = &mut ctx.accounts.user_staking_account;
+        let old_boost_bps = user_staking_account.boost_bps;
+        user_staking_account.boost_bps =
+            utils::calculate_boost_bps(&staking_pool.boost_config, boost_account)?;
+
+        emit!(BoostUpdatedEvent {
+            user: ctx.accounts.user.key(),
+            nft_mint: boost_account.nft_mint,
+            locked_amount: boost_account.locked_amount,
+            old_boost_bps,
+            new_boost_bps: user_staking_account.boost_bps,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn detach_boost_nft(ctx: Context<DetachBoostNft>) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let staking_pool = &ctx.accounts.staking_pool;
+
+        // Rewards up to today are earned with the old boost
+        utils::settle_all_user_rewards(
+            ctx.remaining_accounts,
+            &ctx.accounts.user_staking_account,
+            staking_pool,
+            &staking_pool.key(),
+            ctx.program_id,
+            current_time,
+        )?;
+
+        let boost_account_key = ctx.accounts.boost_account.key();
+        let seeds = &[
+            b"boost_nft_vault",
+            boost_account_key.as_ref(),
+            &[ctx.bumps.nft_vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.nft_vault.to_account_info(),
+            to: ctx.accounts.user_nft_account.to_account_info(),
+            authority: ctx.accounts.nft_vault.to_account_info(),
+            mint: ctx.accounts.nft_mint.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        transfer_checked(cpi_ctx, 1, 0)?;
+
+        // Return the escrow rent so a new NFT can be attached later
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.nft_vault.to_account_info(),
+            destination: ctx.accounts.user.to_account_info(),
+            authority: ctx.accounts.nft_vault.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        close_account(cpi_ctx)?;
+
+        let boost_account = &mut ctx.accounts.boost_account;
+        let nft_mint = boost_account.nft_mint;
+        boost_account.nft_mint = Pubkey::default();
+
+        let user_staking_account = &mut ctx.accounts.user_staking_account;
+        let old_boost_bps = user_staking_account.boost_bps;
+        user_staking_account.boost_bps =
+            utils::calculate_boost_bps(&staking_pool.boost_config, boost_account)?;
+
+        emit!(BoostUpdatedEvent {
+            user: ctx.accounts.user.key(),
+            nft_mint,
+            locked_amount: boost_account.locked_amount,
+            old_boost_bps,
+            new_boost_bps: user_staking_account.boost_bps,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn lock_boost_tokens(ctx: Context<LockBoostTokens>, amount: u64) -> Result<()> {
+        require!(amount > 0, StakingError::InvalidAmount);
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let staking_pool = &ctx.accounts.staking_pool;
+
+        // Rewards up to today are earned with the old boost
+        utils::settle_all_user_rewards(
+            ctx.remaining_accounts,
+            &ctx.accounts.user_staking_account,
+            staking_pool,
+            &staking_pool.key(),
+            ctx.program_id,
+            current_time,
+        )?;
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.user_boost_token_account.to_account_info(),
+            to: ctx.accounts.boost_vault.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+            mint: ctx.accounts.boost_mint.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        transfer_checked(cpi_ctx, amount, ctx.accounts.boost_mint.decimals)?;
+
+        let boost_account = &mut ctx.accounts.boost_account;
+        boost_account.owner = ctx.accounts.user.key();
+        boost_account.locked_amount = boost_account
+            .locked_amount
+            .checked_add(amount)
+            .ok_or(StakingError::Overflow)?;
+
+        let user_staking_account = &mut ctx.accounts.user_staking_account;
+        let old_boost_bps = user_staking_account.boost_bps;
+        user_staking_account.boost_bps =
+            utils::calculate_boost_bps(&staking_pool.boost_config, boost_account)?;
+
+        emit!(BoostUpdatedEvent {
+            user: ctx.accounts.user.key(),
+            nft_mint: boost_account.nft_mint,
+            locked_amount: boost_account.locked_amount,
+            old_boost_bps,
+            new_boost_bps: user_staking_account.boost_bps,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn unlock_boost_tokens(ctx: Context<UnlockBoostTokens>, amount: u64) -> Result<()> {
+        require!(
+            amount > 0 && amount <= ctx.accounts.boost_account.locked_amount,
+            StakingError::InvalidAmount
+        );
+
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let staking_pool = &ctx.accounts.staking_pool;
+
+        // Rewards up to today are earned with the old boost
+        utils::settle_all_user_rewards(
+            ctx.remaining_accounts,
+            &ctx.accounts.user_staking_account,
+            staking_pool,
+            &staking_pool.key(),
+            ctx.program_id,
+            current_time,
+        )?;
+
+        let staking_pool_key = staking_pool.key();
+        let seeds = &[
+            b"boost_vault",
+            staking_pool_key.as_ref(),
+            &[ctx.bumps.boost_vault],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.boost_vault.to_account_info(),
+            to: ctx.accounts.user_boost_token_account.to_account_info(),
+            authority: ctx.accounts.boost_vault.to_account_info(),
+            mint: ctx.accounts.boost_mint.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        transfer_checked(cpi_ctx, amount, ctx.accounts.boost_mint.decimals)?;
+
+        let boost_account = &mut ctx.accounts.boost_account;
+        boost_account.locked_amount = boost_account
+            .locked_amount
+            .checked_sub(amount)
+            .ok_or(StakingError::Underflow)?;
+
+        let user_staking_account = &mut ctx.accounts.user_staking_account;
+        let old_boost_bps = user_staking_account.boost_bps;
+        user_staking_account.boost_bps =
+            utils::calculate_boost_bps(&staking_pool.boost_config, boost_account)?;
+
+        emit!(BoostUpdatedEvent {
+            user: ctx.accounts.user.key(),
+            nft_mint: boost_account.nft_mint,
+            locked_amount: boost_account.locked_amount,
+            old_boost_bps,
+            new_boost_bps: user_staking_account.boost_bps,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless: applies the current boost_config to a user's boost
+    pub fn refresh_boost(ctx: Context<RefreshBoost>) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let staking_pool = &ctx.accounts.staking_pool;
+
+        // Rewards up to today are earned with the old boost
+        utils::settle_all_user_rewards(
+            ctx.remaining_accounts,
+            &ctx.accounts.user_staking_account,
+            staking_pool,
+            &staking_pool.key(),
+            ctx.program_id,
+            current_time,
+        )?;
+
+        let boost_account = &ctx.accounts.boost_account;
+        let user_staking_account = &mut ctx.accounts.user_staking_account;
+        let old_boost_bps = user_staking_account.boost_bps;
+        user_staking_account.boost_bps =
+            utils::calculate_boost_bps(&staking_pool.boost_config, boost_account)?;
+
+        emit!(BoostUpdatedEvent {
+            user: user_staking_account.owner,
+            nft_mint: boost_account.nft_mint,
+            locked_amount: boost_account.locked_amount,
+            old_boost_bps,
+            new_boost_bps: user_staking_account.boost_bps,
+            timestamp: current_time,
+        });
 
         Ok(())
     }
 }
+
+// Shared by stake and stake_for: the payer provides tokens and rent, the position belongs to owner
+#[allow(clippy::too_many_arguments)]
+fn process_stake<'info>(
+    payer: &Signer<'info>,
+    owner: Pubkey,
+    staking_pool: &mut Account<'info, StakingPool>,
+    user_staking_account: &mut Account<'info, UserStakingAccount>,
+    stake_entry_info: &AccountInfo<'info>,
+    source_token_account: &InterfaceAccount<'info, TokenAccount>,
+    stake_vault: &mut InterfaceAccount<'info, TokenAccount>,
+    stake_mint: &InterfaceAccount<'info, Mint>,
+    system_program: &Program<'info, System>,
+    token_program: &Interface<'info, TokenInterface>,
+    program_id: &Pubkey,
+    amount: u64,
+    duration_months: u8,
+    referrer: Pubkey,
+) -> Result<u64> {
+    require!(
+        duration_months == utils::FLEXIBLE_DURATION_MONTHS
+            || duration_months == 3
+            || duration_months == 6
+            || duration_months == 9
+            || duration_months == 12,
+        StakingError::InvalidDuration
+    );
+    utils::validate_stake_caps(
+        staking_pool,
+        user_staking_account.total_staked,
+        duration_months,
+        amount,
+    )?;
+
+    let clock = Clock::get()?;
+    let current_time = clock.unix_timestamp;
+
+    // Check if reward pool has enough tokens for potential rewards
+    let available_rewards = utils::get_available_rewards(staking_pool, current_time)?;
+
+    require!(available_rewards > 0, StakingError::RewardPoolExhausted);
+
+    // Transfer tokens from payer to stake vault
+    let cpi_accounts = TransferChecked {
+        from: source_token_account.to_account_info(),
+        to: stake_vault.to_account_info(),
+        authority: payer.to_account_info(),
+        mint: stake_mint.to_account_info(),
+    };
+
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+    let before = stake_vault.amount;
+    transfer_checked(cpi_ctx, amount, utils::DECIMALS)?;
+    stake_vault.reload()?;
+    let after = stake_vault.amount;
+    #[allow(clippy::manual_saturating_arithmetic)]
+    let delta = after.checked_sub(before).unwrap_or(0);
+
+    // Initialize or update user staking account
+    if user_staking_account.owner == Pubkey::default() {
+        user_staking_account.owner = owner;
+        user_staking_account.stake_count = 0;
+        user_staking_account.total_staked = 0;
+        user_staking_account.total_claimed = 0;
+    }
+
+    // Auto-calculate stake index
+    let stake_index = user_staking_account.stake_count;
+
+    // Derive expected PDA and verify
+    let staking_pool_key = staking_pool.key();
+    let stake_index_bytes = stake_index.to_le_bytes();
+
+    let (expected_key, _bump) = Pubkey::find_program_address(
+        &[
+            b"stake_entry",
+            owner.as_ref(),
+            staking_pool_key.as_ref(),
+            &stake_index_bytes,
+        ],
+        program_id,
+    );
+
+    require!(
+        stake_entry_info.key() == expected_key,
+        StakingError::InvalidStakeIndex
+    );
+
+    // Create and initialize the stake entry account
+    let stake_entry_size = 8 + StakeEntry::INIT_SPACE;
+    let lamports = Rent::get()?.minimum_balance(stake_entry_size);
+
+    let seeds = &[
+        b"stake_entry",
+        owner.as_ref(),
+        staking_pool_key.as_ref(),
+        &stake_index_bytes,
+        &[_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    anchor_lang::solana_program::program::invoke_signed(
+        &anchor_lang::solana_program::system_instruction::create_account(
+            &payer.key(),
+            &stake_entry_info.key(),
+            lamports,
+            stake_entry_size as u64,
+            program_id,
+        ),
+        &[
+            payer.to_account_info(),
+            stake_entry_info.to_account_info(),
+            system_program.to_account_info(),
+        ],
+        signer,
+    )?;
+
+    // Weight in the bonus pot, starting from the current accumulator
+    let weighted_amount = utils::calculate_weighted_amount(staking_pool, delta, duration_months)?;
+    let bonus_debt = utils::get_bonus_debt(staking_pool, weighted_amount)?;
+
+    // Write the stake entry data
+    let mut data = stake_entry_info.try_borrow_mut_data()?;
+
+    let stake_entry = StakeEntry {
+        owner,
+        stake_index,
+        amount: delta,
+        start_time: current_time,
+        duration_months,
+        last_claim_time: current_time,
+        last_claim_week: utils::get_claim_epoch_number(staking_pool, current_time)?,
+        is_active: true,
+        total_claimed: 0,
+        weighted_amount,
+        bonus_debt,
+        accrued_rewards: 0,
+        accrued_through_day: 0,
+        position_mint: Pubkey::default(),
+        unbonding_amount: 0,
+        unlock_time: 0,
+        auto_renew: false,
+        renewal_count: 0,
+        referrer,
+        referral_accrued: 0,
+        withheld_bonus: 0,
+        pending_rewards: 0,
+    };
+
+    let mut writer = data.as_mut();
+    stake_entry.try_serialize(&mut writer)?;
+
+    // Update user staking account
+    user_staking_account.stake_count = user_staking_account
+        .stake_count
+        .checked_add(1)
+        .ok_or(StakingError::Overflow)?;
+    user_staking_account.total_staked = user_staking_account
+        .total_staked
+        .checked_add(delta)
+        .ok_or(StakingError::Overflow)?;
+
+    // Update staking pool
+    staking_pool.total_staked = staking_pool
+        .total_staked
+        .checked_add(delta)
+        .ok_or(StakingError::Overflow)?;
+    let tier = utils::get_tier_slot(duration_months)?;
+    staking_pool.tier_staked[tier] = staking_pool.tier_staked[tier]
+        .checked_add(delta)
+        .ok_or(StakingError::Overflow)?;
+    staking_pool.total_weighted_stake = staking_pool
+        .total_weighted_stake
+        .checked_add(weighted_amount)
+        .ok_or(StakingError::Overflow)?;
+    utils::add_stake_voting_power(staking_pool, &stake_entry, current_time)?;
+
+    // Update daily rate for current day
+    utils::update_daily_rate(staking_pool, current_time)?;
+
+    emit!(StakeEvent {
+        user: owner,
+        amount: delta,
+        duration_months,
+        timestamp: current_time,
+    });
+
+    Ok(delta)
+}
+
+// Moves the liquid stake rewards from the reward vault into the stake vault, raising the exchange rate
+#[allow(clippy::too_many_arguments)]
+fn compound_liquid_rewards<'info>(
+    staking_pool: &mut Account<'info, StakingPool>,
+    reward_vault: &InterfaceAccount<'info, TokenAccount>,
+    reward_vault_bump: u8,
+    stake_vault: &InterfaceAccount<'info, TokenAccount>,
+    stake_mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    receipt_supply: u64,
+    current_time: i64,
+) -> Result<u64> {
+    let rewards = utils::calculate_liquid_rewards(staking_pool, current_time)?;
+    staking_pool.liquid_compounded_day =
+        utils::get_day_index(current_time, staking_pool.program_start_time)?;
+
+    if rewards == 0 {
+        return Ok(0);
+    }
+
+    let seeds = &[
+        b"reward_vault",
+        staking_pool.stake_mint.as_ref(),
+        &[reward_vault_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = TransferChecked {
+        from: reward_vault.to_account_info(),
+        to: stake_vault.to_account_info(),
+        authority: reward_vault.to_account_info(),
+        mint: stake_mint.to_account_info(),
+    };
+
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+    transfer_checked(cpi_ctx, rewards, utils::DECIMALS)?;
+
+    staking_pool.liquid_staked = staking_pool
+        .liquid_staked
+        .checked_add(rewards)
+        .ok_or(StakingError::Overflow)?;
+    staking_pool.total_staked = staking_pool
+        .total_staked
+        .checked_add(rewards)
+        .ok_or(StakingError::Overflow)?;
+    staking_pool.total_rewards_distributed = staking_pool
+        .total_rewards_distributed
+        .checked_add(rewards)
+        .ok_or(StakingError::Overflow)?;
+    staking_pool.liquid_exchange_rate =
+        utils::get_liquid_exchange_rate(staking_pool.liquid_staked, receipt_supply)?;
+
+    emit!(LiquidCompoundedEvent {
+        rewards,
+        liquid_staked: staking_pool.liquid_staked,
+        exchange_rate: staking_pool.liquid_exchange_rate,
+        timestamp: current_time,
+    });
+
+    Ok(rewards)
+}
+
+// Shared by redeem_liquid and request_redeem_liquid: compounds, burns the receipts and removes
+// their share of the liquid stake from the pool, the tokens stay in the stake vault
+#[allow(clippy::too_many_arguments)]
+fn burn_liquid_receipts<'info>(
+    staking_pool: &mut Account<'info, StakingPool>,
+    liquid_mint: &mut InterfaceAccount<'info, Mint>,
+    user_liquid_account: &InterfaceAccount<'info, TokenAccount>,
+    user: &Signer<'info>,
+    reward_vault: &InterfaceAccount<'info, TokenAccount>,
+    reward_vault_bump: u8,
+    stake_vault: &InterfaceAccount<'info, TokenAccount>,
+    stake_mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    receipt_amount: u64,
+    current_time: i64,
+) -> Result<u64> {
+    require!(receipt_amount > 0, StakingError::InvalidAmount);
+
+    // Compound first so the receipts include rewards up to now
+    compound_liquid_rewards(
+        staking_pool,
+        reward_vault,
+        reward_vault_bump,
+        stake_vault,
+        stake_mint,
+        token_program,
+        liquid_mint.supply,
+        current_time,
+    )?;
+
+    let amount = (receipt_amount as u128)
+        .checked_mul(staking_pool.liquid_staked as u128)
+        .ok_or(StakingError::Overflow)?
+        .checked_div(liquid_mint.supply as u128)
+        .ok_or(StakingError::DivisionByZero)? as u64;
+
+    let cpi_accounts = Burn {
+        mint: liquid_mint.to_account_info(),
+        from: user_liquid_account.to_account_info(),
+        authority: user.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(token_program.to_account_info(), cpi_accounts);
+
+    burn(cpi_ctx, receipt_amount)?;
+    liquid_mint.reload()?;
+
+    staking_pool.liquid_staked = staking_pool
+        .liquid_staked
+        .checked_sub(amount)
+        .ok_or(StakingError::Underflow)?;
+    staking_pool.total_staked = staking_pool
+        .total_staked
+        .checked_sub(amount)
+        .ok_or(StakingError::Underflow)?;
+    staking_pool.liquid_exchange_rate =
+        utils::get_liquid_exchange_rate(staking_pool.liquid_staked, liquid_mint.supply)?;
+
+    Ok(amount)
+}
+
+// Shared by unstake and request_unstake: pays rewards, applies the penalty and closes the stake,
+// when unbonding the principal stays in the stake vault until withdraw
+fn process_unstake(ctx: Context<Unstake>, unbond: bool) -> Result<()> {
+    let clock = Clock::get()?;
+    let current_time = clock.unix_timestamp;
+
+    let stake_entry = &mut ctx.accounts.stake_entry;
+    require!(stake_entry.is_active, StakingError::StakeNotActive);
+    utils::validate_stake_authority(
+        stake_entry,
+        &ctx.accounts.user.key(),
+        ctx.accounts.position_token_account.as_ref(),
+    )?;
+
+    let staking_pool = &ctx.accounts.staking_pool;
+
+    // Calculate any remaining rewards
+    let rewards = utils::calculate_claimable_rewards(
+        stake_entry,
+        staking_pool,
+        utils::get_stake_boost_bps(stake_entry, &ctx.accounts.user_staking_account),
+        current_time,
+        true, // unstaking
+    )?;
+    let bonus = utils::calculate_pending_bonus(stake_entry, staking_pool)?;
+
+    // Denied wallets get their principal back, rewards and bonus stay recorded on the stake
+    // and are paid by claim_all once the wallet is removed from the deny-list.
+    // A position NFT would be burned with the principal, so it has to wait for the removal
+    let denied = !ctx.accounts.denylist_entry.data_is_empty();
+    require!(
+        !denied || !utils::is_position_tokenized(stake_entry),
+        StakingError::WalletDenied
+    );
+    let (rewards, bonus, withheld_rewards, withheld_bonus) = if denied {
+        (0, 0, rewards, bonus)
+    } else {
+        (rewards, bonus, 0, 0)
+    };
+    let referral = utils::accrue_referral_reward(stake_entry, staking_pool, rewards)?;
+
+    // Calculate penalty for early unstaking
+    let lock_start_time =
+        utils::get_lock_start_time(stake_entry, staking_pool.month_convention, current_time);
+    let lock_end_time =
+        utils::get_lock_end_time(stake_entry, staking_pool.month_convention, current_time);
+    let lock_duration_days = (lock_end_time - lock_start_time) / 86400;
+    let elapsed_time = current_time - lock_start_time;
+    let elapsed_days = elapsed_time / 86400; // seconds to days
+
+    let mut penalty_amount = 0u64;
+    let mut user_receive_amount = stake_entry.amount;
+
+    // Apply penalty if unstaking before lock period ends, flexible stakes have no lock
+    if !utils::is_flexible_stake(stake_entry) && elapsed_days < lock_duration_days {
+        // Maximum penalty (20% by default), decreasing linearly to 0%
+        let penalty_rate = staking_pool.max_penalty_percent as i64
+            * (lock_duration_days - elapsed_days)
+            / lock_duration_days;
+        penalty_amount = stake_entry
+            .amount
+            .checked_mul(penalty_rate as u64)
+            .ok_or(StakingError::Overflow)?
+            .checked_div(100)
+            .ok_or(StakingError::Overflow)?;
+
+        user_receive_amount = stake_entry
+            .amount
+            .checked_sub(penalty_amount)
+            .ok_or(StakingError::Underflow)?;
+    }
+
+    let seeds = &[
+        b"stake_vault",
+        staking_pool.stake_mint.as_ref(),
+        &[ctx.bumps.stake_vault],
+    ];
+    let signer = &[&seeds[..]];
+
+    // Transfer staked tokens back to user (minus penalty), unbonding stakes wait for withdraw
+    if !unbond {
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.stake_vault.to_account_info(),
+            to: ctx.accounts.user_token_account.to_account_info(),
+            authority: ctx.accounts.stake_vault.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        transfer_checked(cpi_ctx, user_receive_amount, utils::DECIMALS)?;
+    }
+
+    // In redistribution mode the penalty goes to the bonus pot of the remaining stakers,
+    // otherwise (or when nobody is left) it is routed according to the fee split
+    let remaining_weighted_stake = staking_pool
+        .total_weighted_stake
+        .checked_sub(stake_entry.weighted_amount)
+        .ok_or(StakingError::Underflow)?;
+    let bonus_pot_amount = if staking_pool.penalty_mode == PenaltyMode::Redistribute
+        && remaining_weighted_stake > 0
+    {
+        penalty_amount
+    } else {
+        0
+    };
+    let (treasury_amount, buyback_amount, reward_vault_amount) = utils::split_fee(
+        penalty_amount - bonus_pot_amount,
+        &staking_pool.fee_split,
+    )?;
+
+    if treasury_amount > 0 {
+        let penalty_cpi_accounts = TransferChecked {
+            from: ctx.accounts.stake_vault.to_account_info(),
+            to: ctx.accounts.treasury_token_account.to_account_info(),
+            authority: ctx.accounts.stake_vault.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+        };
+
+        let penalty_cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            penalty_cpi_accounts,
+            signer,
+        );
+
+        transfer_checked(penalty_cpi_ctx, treasury_amount, utils::DECIMALS)?;
+    }
+
+    if buyback_amount > 0 {
+        let buyback_token_account = ctx
+            .accounts
+            .buyback_token_account
+            .as_ref()
+            .ok_or(StakingError::InvalidFeeDestination)?;
+
+        let buyback_cpi_accounts = TransferChecked {
+            from: ctx.accounts.stake_vault.to_account_info(),
+            to: buyback_token_account.to_account_info(),
+            authority: ctx.accounts.stake_vault.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+        };
+
+        let buyback_cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            buyback_cpi_accounts,
+            signer,
+        );
+
+        transfer_checked(buyback_cpi_ctx, buyback_amount, utils::DECIMALS)?;
+    }
+
+    let recycled_amount = reward_vault_amount
+        .checked_add(bonus_pot_amount)
+        .ok_or(StakingError::Overflow)?;
+    if recycled_amount > 0 {
+        let recycle_cpi_accounts = TransferChecked {
+            from: ctx.accounts.stake_vault.to_account_info(),
+            to: ctx.accounts.reward_vault.to_account_info(),
+            authority: ctx.accounts.stake_vault.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+        };
+
+        let recycle_cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            recycle_cpi_accounts,
+            signer,
+        );
+
+        transfer_checked(recycle_cpi_ctx, recycled_amount, utils::DECIMALS)?;
+    }
+
+    // Transfer any remaining rewards and bonus from reward vault
+    let payout = rewards.checked_add(bonus).ok_or(StakingError::Overflow)?;
+    if payout > 0 {
+        let reward_seeds = &[
+            b"reward_vault",
+            staking_pool.stake_mint.as_ref(),
+            &[ctx.bumps.reward_vault],
+        ];
+        let reward_signer = &[&reward_seeds[..]];
+
+        let transfer_accounts = TransferChecked {
+            from: ctx.accounts.reward_vault.to_account_info(),
+            to: ctx.accounts.user_reward_account.to_account_info(),
+            authority: ctx.accounts.reward_vault.to_account_info(),
+            mint: ctx.accounts.stake_mint.to_account_info(),
+        };
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_accounts,
+            reward_signer,
+        );
+
+        transfer_checked(transfer_ctx, payout, utils::DECIMALS)?;
+    }
+
+    // The position NFT is redeemed together with the principal
+    if !unbond && utils::is_position_tokenized(stake_entry) {
+        let position_token_account = ctx
+            .accounts
+            .position_token_account
+            .as_ref()
+            .ok_or(StakingError::InvalidPositionToken)?;
+        let position_mint = ctx
+            .accounts
+            .position_mint
+            .as_ref()
+            .ok_or(StakingError::InvalidPositionToken)?;
+
+        let burn_accounts = Burn {
+            mint: position_mint.to_account_info(),
+            from: position_token_account.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+
+        let burn_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), burn_accounts);
+
+        burn(burn_ctx, 1)?;
+    }
+
+    // Update staking pool
+    let staking_pool = &mut ctx.accounts.staking_pool;
+    staking_pool.total_staked = staking_pool
+        .total_staked
+        .checked_sub(stake_entry.amount)
+        .ok_or(StakingError::Underflow)?;
+    let tier = utils::get_tier_slot(stake_entry.duration_months)?;
+    staking_pool.tier_staked[tier] = staking_pool.tier_staked[tier]
+        .checked_sub(stake_entry.amount)
+        .ok_or(StakingError::Underflow)?;
+    staking_pool.total_rewards_distributed = staking_pool
+        .total_rewards_distributed
+        .checked_add(rewards)
+        .ok_or(StakingError::Overflow)?;
+    // Penalty sent to the reward vault is recycled as funding
+    staking_pool.total_funded = staking_pool
+        .total_funded
+        .checked_add(reward_vault_amount)
+        .ok_or(StakingError::Overflow)?;
+    // Withheld bonus stays in the pot until it is claimed
+    staking_pool.bonus_pot = staking_pool
+        .bonus_pot
+        .checked_sub(bonus)
+        .ok_or(StakingError::Underflow)?;
+    staking_pool.total_bonus_distributed = staking_pool
+        .total_bonus_distributed
+        .checked_add(bonus)
+        .ok_or(StakingError::Overflow)?;
+    staking_pool.total_weighted_stake = remaining_weighted_stake;
+    utils::add_referral_pending(staking_pool, referral)?;
+    let unlock_time = current_time
+        .checked_add(staking_pool.unbonding_cooldown)
+        .ok_or(StakingError::Overflow)?;
+    if unbond {
+        staking_pool.total_unbonding = staking_pool
+            .total_unbonding
+            .checked_add(user_receive_amount)
+            .ok_or(StakingError::Overflow)?;
+    }
+    utils::remove_stake_voting_power(staking_pool, stake_entry, current_time)?;
+    if bonus_pot_amount > 0 {
+        utils::distribute_bonus(staking_pool, bonus_pot_amount)?;
+    }
+
+    // Update user staking account of the original owner
+    let user_staking_account = &mut ctx.accounts.user_staking_account;
+    user_staking_account.total_staked = user_staking_account
+        .total_staked
+        .checked_sub(stake_entry.amount)
+        .ok_or(StakingError::Underflow)?;
+    if !utils::is_position_tokenized(stake_entry) {
+        user_staking_account.total_claimed = user_staking_account
+            .total_claimed
+            .checked_add(payout)
+            .ok_or(StakingError::Overflow)?;
+    }
+
+    // Mark stake as inactive, unbonding stakes no longer accrue rewards
+    stake_entry.is_active = false;
+    if unbond {
+        stake_entry.unbonding_amount = user_receive_amount;
+        stake_entry.unlock_time = unlock_time;
+    }
+    stake_entry.accrued_rewards = withheld_rewards;
+    stake_entry.pending_rewards = 0;
+    stake_entry.withheld_bonus = withheld_bonus;
+    stake_entry.total_claimed = stake_entry
+        .total_claimed
+        .checked_add(payout)
+        .ok_or(StakingError::Overflow)?;
+
+    // Update daily rate
+    utils::update_daily_rate(staking_pool, current_time)?;
+
+    if penalty_amount > 0 {
+        emit!(PenaltyDistributedEvent {
+            user: ctx.accounts.user.key(),
+            treasury_amount,
+            buyback_amount,
+            reward_vault_amount,
+            bonus_pot_amount,
+            timestamp: current_time,
+        });
+    }
+
+    if unbond {
+        emit!(UnstakeRequestedEvent {
+            user: ctx.accounts.user.key(),
+            stake_index: stake_entry.stake_index,
+            amount: user_receive_amount,
+            rewards,
+            bonus,
+            unlock_time,
+            timestamp: current_time,
+        });
+    } else {
+        emit!(UnstakeEvent {
+            user: ctx.accounts.user.key(),
+            amount: stake_entry.amount,
+            rewards,
+            bonus,
+            timestamp: current_time,
+        });
+    }
+
+    Ok(())
+}
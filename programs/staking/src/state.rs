@@ -1,5 +1,7 @@
 use anchor_lang::prelude::*;
-use crate::utils::MAX_DAILY_RATES;
+use crate::utils::{
+    EMISSION_MONTHS, MAX_DAILY_RATES, MAX_RATE_OVERRIDES, MAX_WEIGHT_CHANGES, TIER_COUNT, TIER_SLOTS, VE_WEEKS,
+};
 
 // State accounts
 #[account]
@@ -16,6 +18,171 @@ pub struct StakingPool {
     pub normalization_k: u128,
     #[max_len(MAX_DAILY_RATES)]
     pub daily_rates: Vec<u64>,
+    pub total_funded: u64,
+    pub reward_funder: Pubkey, // Pubkey::default() - anyone can fund
+    pub timelock_delay: i64,
+    pub pending_withdrawal_amount: u64,
+    pub pending_withdrawal_eta: i64,
+    pub tier_weights: [u16; TIER_COUNT], // 3, 6, 9, 12 months (10 = 1.0x)
+    pub max_penalty_percent: u8,
+    pub emission_schedule: [u64; EMISSION_MONTHS], // cumulative released rewards by month
+    pub change_count: u64,
+    #[max_len(MAX_RATE_OVERRIDES)]
+    pub daily_rate_overrides: Vec<DailyRateOverride>, // daily_rates keeps the computed value
+    pub buyback_address: Pubkey,
+    pub fee_split: FeeSplit,
+    pub penalty_mode: PenaltyMode,
+    pub total_weighted_stake: u128,
+    pub bonus_per_weight: u128, // accumulated bonus per weighted token, scaled by BONUS_PRECISION
+    pub bonus_pot: u64,         // redistributed penalties held in the reward vault, not yet claimed
+    pub total_bonus_distributed: u64,
+    pub boost_config: BoostConfig,
+    pub ve_bias: u128,          // total voting power × MAX_LOCK_SECONDS at ve_checkpoint_time
+    pub ve_slope: u64,          // decay of ve_bias per second
+    pub ve_checkpoint_time: i64,
+    pub ve_slope_changes: [u64; VE_WEEKS], // slope dropped at each week boundary from program start
+    pub governance_realm: Pubkey,     // SPL Governance realm, Pubkey::default() - voter weight disabled
+    pub governing_token_mint: Pubkey, // mint of the realm the voter weight is reported for
+    pub liquid_mint: Pubkey,          // liquid staking receipt mint, Pubkey::default() - disabled
+    pub liquid_staked: u64,           // principal plus compounded rewards backing the receipts
+    pub liquid_exchange_rate: u128,   // stake tokens per receipt (scaled by EXCHANGE_RATE_PRECISION)
+    pub liquid_compounded_day: u64,   // first day not yet compounded
+    pub unbonding_cooldown: i64,      // seconds between request_unstake and withdraw, 0 - unstake directly
+    pub total_unbonding: u64,         // principal waiting in the stake vault for withdraw
+    pub flexible_weight: u16,         // flexible (no lock) tier, same scale as tier_weights
+    pub ve_permanent: u64,            // constant voting power of auto-renewing stakes
+    pub matured_weight: u16,          // earned after the lock end, 0 - matured stakes earn nothing
+    pub total_matured: u64,           // part of total_staked past its lock end (from the week boundary after it)
+    pub month_convention: MonthConvention, // lock ends and emission months, fixed at initialize
+    pub claim_epoch: ClaimEpoch,      // rewards are claimable for completed epochs
+    pub claim_epoch_offset: i64,      // epochs start this many seconds before program start
+    pub accrual_mode: AccrualMode,
+    pub staking_caps: StakingCaps,
+    pub tier_staked: [u64; TIER_SLOTS], // staked per tier, flexible last
+    pub allowlist_enabled: bool,      // staking requires an AllowlistEntry or a Merkle proof
+    pub allowlist_root: [u8; 32],     // Merkle root of allowed wallets, [0; 32] - entries only
+    pub gatekeeper: Pubkey,           // manages allow and deny-list entries, Pubkey::default() - authority only
+    pub referral_bps: u16,            // referrer share of the rewards claimed by referred stakes
+    pub referral_pending: u64,        // referral rewards accrued on stake entries, held in the reward vault
+    pub rate_snapshot: RateSnapshot,  // inputs of the latest computed daily rate, earlier days are final
+    pub gap_fill_mode: GapFillMode,   // rate of days after the latest computed one
+    pub crank_tip: u64,               // paid from crank_budget to crank_daily_rate calls that finalize days
+    pub crank_budget: u64,            // tips held in the reward vault
+    pub rate_smoothing: RateSmoothing,
+    #[max_len(MAX_DAILY_RATES)]
+    pub raw_daily_rates: Vec<u64>, // computed rates before rate_smoothing, daily_rates keeps the smoothed value
+    #[max_len(MAX_WEIGHT_CHANGES)]
+    pub weight_history: Vec<AccrualWeights>, // weights replaced by parameter changes, oldest first
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug, Default)]
+pub struct BoostConfig {
+    pub nft_collection: Pubkey, // Token 2022 group of eligible NFTs, Pubkey::default() - disabled
+    pub nft_boost_bps: u16,
+    pub boost_mint: Pubkey, // secondary boost token, Pubkey::default() - disabled
+    pub token_boost_bps: u16, // boost per token_boost_unit locked
+    pub token_boost_unit: u64,
+    pub max_boost_bps: u16, // per user cap
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug)]
+pub enum MonthConvention {
+    ThirtyDays, // a month is exactly 30 days
+    Calendar,   // calendar months (UTC) from the start timestamp
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug)]
+pub enum ClaimEpoch {
+    Daily,
+    Weekly,
+    Monthly, // in the pool's month convention
+}
+
+// Stake limits, 0 disables a cap
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug, Default)]
+pub struct StakingCaps {
+    pub min_stake_amount: u64,
+    pub max_stake_per_user: u64,               // against UserStakingAccount.total_staked
+    pub max_stake_per_tier: [u64; TIER_SLOTS], // 3, 6, 9, 12 months and flexible
+    pub pool_cap: u64,                         // against StakingPool.total_staked
+}
+
+// Returned by get_staking_capacity, u64::MAX where no cap applies
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StakingCapacity {
+    pub min_stake_amount: u64,
+    pub user_remaining: u64,
+    pub tier_remaining: [u64; TIER_SLOTS],
+    pub pool_remaining: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug)]
+pub enum AccrualMode {
+    WholeDays, // complete days only, the unfinished epoch is forfeited on unstake
+    ProRata,   // per second, partial first and last days, the unfinished epoch is paid on unstake
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug)]
+pub enum GapFillMode {
+    CarryForward, // the latest computed rate
+    Zero,         // nothing, rewards accrue only on days with a computed rate
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug, Default)]
+pub enum SmoothingMode {
+    #[default]
+    Off, // the computed rate is used as is
+    Ema, // exponential moving average over ema_days
+}
+
+// Weights days accrue with, the current ones are kept in StakingPool
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug)]
+pub struct AccrualWeights {
+    pub until_day: u64, // first day accrued with the next weights
+    pub tier_weights: [u16; TIER_COUNT],
+    pub flexible_weight: u16,
+    pub matured_weight: u16,
+    pub normalization_k: u128,
+}
+
+// Applied to the computed daily rate in the order EMA, daily change limit, bounds.
+// 0 disables a limit
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug, Default)]
+pub struct RateSmoothing {
+    pub mode: SmoothingMode,
+    pub ema_days: u16,              // EMA weight of the new rate is 2 / (ema_days + 1)
+    pub max_daily_change_bps: u16,  // against the previous day's rate
+    pub min_rate: u64,              // same precision as daily_rates
+    pub max_rate: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug)]
+pub enum PenaltyMode {
+    Route,        // penalties are routed by fee_split
+    Redistribute, // penalties are shared pro-rata by the remaining stakers
+}
+
+// Penalty routing in basis points, must add up to 10_000
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug)]
+pub struct FeeSplit {
+    pub treasury_bps: u16,
+    pub buyback_bps: u16,
+    pub reward_vault_bps: u16,
+}
+
+// Reported in DailyRateFinalizedEvent once the day is over
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug, Default)]
+pub struct RateSnapshot {
+    pub day_index: u64,
+    pub total_staked: u64,
+    pub earning_stake: u64, // stake the rate is spread over
+    pub available_rewards: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug)]
+pub struct DailyRateOverride {
+    pub day_index: u64,
+    pub rate: u64,
 }
 
 #[account]
@@ -25,6 +192,58 @@ pub struct UserStakingAccount {
     pub stake_count: u64,
     pub total_staked: u64,
     pub total_claimed: u64,
+    pub boost_bps: u16,
+    pub stake_for_funder: Pubkey, // wallet allowed to stake_for this user, Pubkey::default() - nobody
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct BoostAccount {
+    pub owner: Pubkey,
+    pub nft_mint: Pubkey, // Pubkey::default() - no NFT attached
+    pub locked_amount: u64,
+}
+
+// Seeds: "allowlist", staking pool, wallet
+#[account]
+#[derive(InitSpace)]
+pub struct AllowlistEntry {
+    pub staking_pool: Pubkey,
+    pub wallet: Pubkey,
+    pub added_by: Pubkey,
+    pub added_at: i64,
+}
+
+// Seeds: "denylist", staking pool, wallet - blocks staking and claiming while it exists
+#[account]
+#[derive(InitSpace)]
+pub struct DenylistEntry {
+    pub staking_pool: Pubkey,
+    pub wallet: Pubkey,
+    pub added_by: Pubkey,
+    pub added_at: i64,
+}
+
+// Redeemed liquid stake waiting for the unbonding cooldown
+// Seeds: "liquid_unbonding", staking pool, owner
+#[account]
+#[derive(InitSpace)]
+pub struct LiquidUnbonding {
+    pub staking_pool: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub unlock_time: i64, // a new request restarts the cooldown for the whole amount
+}
+
+// Seeds: "referrer", staking pool, referrer
+#[account]
+#[derive(InitSpace)]
+pub struct ReferrerAccount {
+    pub staking_pool: Pubkey,
+    pub referrer: Pubkey,
+    pub referral_count: u64,
+    pub total_referred: u64, // principal of the referred stakes
+    pub total_claimed: u64,
 }
 
 #[account]
@@ -36,7 +255,89 @@ pub struct StakeEntry {
     pub start_time: i64,
     pub duration_months: u8,
     pub last_claim_time: i64,
-    pub last_claim_week: u64,
+    pub last_claim_week: u64, // claim epoch of the last claim, the name predates configurable epochs
     pub is_active: bool,
     pub total_claimed: u64,
+    pub weighted_amount: u128,
+    pub bonus_debt: u128,
+    pub accrued_rewards: u64,     // settled but not yet claimed
+    pub accrued_through_day: u64, // days before this one are included in accrued_rewards
+    pub position_mint: Pubkey,    // position NFT, Pubkey::default() - held by owner
+    pub unbonding_amount: u64,    // principal left to withdraw after request_unstake
+    pub unlock_time: i64,         // withdraw is allowed from this time
+    pub auto_renew: bool,         // roll into a new lock of the same tier at maturity
+    pub renewal_count: u32,       // lock periods completed before the current one, fixed when renewal is cancelled
+    pub referrer: Pubkey,         // Pubkey::default() - no referrer
+    pub referral_accrued: u64,    // referrer share not yet claimed by the referrer
+    pub withheld_bonus: u64,      // bonus of a stake unstaked while the wallet was denied
+    pub pending_rewards: u64,     // settled days of an unfinished claim epoch, claimable once it ends
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace, Debug)]
+pub enum ParameterChange {
+    NormalizationK { new_k: u128 },
+    TierWeights { weights: [u16; TIER_COUNT] },
+    FlexibleWeight { weight: u16 },
+    MaturedWeight { weight: u16 },
+    AccrualMode { mode: AccrualMode },
+    StakingCaps { caps: StakingCaps },
+    MaxPenalty { percent: u8 },
+    Treasury { treasury: Pubkey },
+    EmissionSchedule { schedule: [u64; EMISSION_MONTHS] },
+    DailyRate { day_index: u64, rate: u64 },
+    TimelockDelay { delay: i64 },
+    FeeRouting { fee_split: FeeSplit, buyback: Pubkey },
+    PenaltyMode { mode: PenaltyMode },
+    BoostConfig { config: BoostConfig },
+    Governance { realm: Pubkey, governing_token_mint: Pubkey },
+    UnbondingCooldown { cooldown: i64 },
+    ReferralBps { bps: u16 },
+    GapFillMode { mode: GapFillMode },
+    CrankTip { tip: u64 },
+    RateSmoothing { config: RateSmoothing },
+    RewardFunder { funder: Pubkey }, // Pubkey::default() - anyone can fund
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct PendingChange {
+    pub staking_pool: Pubkey,
+    pub change_id: u64,
+    pub change: ParameterChange,
+    pub queued_at: i64,
+    pub eta: i64,
+}
+
+
+// SPL Governance voter weight add-in accounts, the Anchor discriminators match the add-in interface
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace, Debug)]
+pub enum VoterWeightAction {
+    CastVote,
+    CommentProposal,
+    CreateGovernance,
+    CreateProposal,
+    SignOffProposal,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct VoterWeightRecord {
+    pub realm: Pubkey,
+    pub governing_token_mint: Pubkey,
+    pub governing_token_owner: Pubkey,
+    pub voter_weight: u64,
+    pub voter_weight_expiry: Option<u64>, // slot after which the weight is stale
+    pub weight_action: Option<VoterWeightAction>,
+    pub weight_action_target: Option<Pubkey>,
+    pub reserved: [u8; 8],
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct MaxVoterWeightRecord {
+    pub realm: Pubkey,
+    pub governing_token_mint: Pubkey,
+    pub max_voter_weight: u64,
+    pub max_voter_weight_expiry: Option<u64>,
+    pub reserved: [u8; 8],
 }
\ No newline at end of file
@@ -1,30 +1,184 @@
 use crate::error::StakingError;
+use crate::events::DailyRateFinalizedEvent;
 use crate::state::*;
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hashv;
+use anchor_spl::token_interface::{Mint, TokenAccount};
+use spl_token_2022::extension::{BaseStateWithExtensions, StateWithExtensions};
+use spl_token_2022::state::Mint as Token2022Mint;
+use spl_token_group_interface::state::TokenGroupMember;
 
 // Constants
 pub const TOTAL_REWARD_POOL: u64 = 250_000_000_000_000_000; // 250M with 9 decimals
 pub const MAX_DAILY_RATES: usize = 370; // Store rates for ~1 year
+pub const MAX_BACKFILL_DAYS: u64 = 32; // days without a rate update filled per call, bounded by the log size
+pub const MAX_RATE_OVERRIDES: usize = 64;
+pub const MAX_WEIGHT_CHANGES: usize = 16; // weight changes whose earlier weights are kept for accrual
+pub const BASIS_POINTS: u64 = 10_000;
+pub const MAX_BOOST_BPS: u64 = 10_000; // up to 2.0x of the tier weight
+pub const DEFAULT_FEE_SPLIT: FeeSplit = FeeSplit {
+    treasury_bps: 10_000,
+    buyback_bps: 0,
+    reward_vault_bps: 0,
+};
 pub const DECIMALS: u8 = 9;
 pub const SECONDS_PER_DAY: i64 = 86400;
 pub const SECONDS_PER_WEEK: i64 = 604800;
+pub const MAX_LOCK_SECONDS: i64 = 12 * 30 * SECONDS_PER_DAY; // lock giving full voting power
+pub const VE_WEEKS: usize = 128; // weeks from program start tracked by the voting power checkpoint
+pub const POSITION_NAME: &str = "REDPANDA Stake";
+pub const LIQUID_DURATION_MONTHS: u8 = FLEXIBLE_DURATION_MONTHS; // liquid stake has no lock, it earns the flexible tier weight
+pub const EXCHANGE_RATE_PRECISION: u128 = 1_000_000_000_000;
+pub const POSITION_SYMBOL: &str = "RPSTAKE";
+pub const DEFAULT_TIMELOCK_DELAY: i64 = 2 * SECONDS_PER_DAY;
+pub const MAX_TIMELOCK_DELAY: i64 = 30 * SECONDS_PER_DAY;
+pub const MAX_UNBONDING_COOLDOWN: i64 = 30 * SECONDS_PER_DAY;
+pub const MAX_REFERRAL_BPS: u16 = 2_000; // 20% of the referred stake's rewards
+pub const TIER_COUNT: usize = 4;
+pub const DEFAULT_TIER_WEIGHTS: [u16; TIER_COUNT] = [10, 15, 20, 30]; // 1.0x, 1.5x, 2.0x, 3.0x
+pub const FLEXIBLE_DURATION_MONTHS: u8 = 0; // no lock, can be unstaked at any time without penalty
+pub const TIER_SLOTS: usize = 5; // lock tiers followed by the flexible tier
+pub const TIER_DURATIONS: [u8; TIER_SLOTS] = [3, 6, 9, 12, FLEXIBLE_DURATION_MONTHS];
+pub const DEFAULT_FLEXIBLE_WEIGHT: u16 = 5; // 0.5x
+pub const DEFAULT_MAX_PENALTY_PERCENT: u8 = 20;
+pub const EMISSION_MONTHS: usize = 12;
+pub const DEFAULT_EMISSION_SCHEDULE: [u64; EMISSION_MONTHS] = [
+    20_833_333_000_000_000,
+    41_666_667_000_000_000,
+    62_500_000_000_000_000,
+    83_333_333_000_000_000,
+    104_166_667_000_000_000,
+    125_000_000_000_000_000,
+    145_833_333_000_000_000,
+    166_666_667_000_000_000,
+    187_500_000_000_000_000,
+    208_333_333_000_000_000,
+    229_166_667_000_000_000,
+    TOTAL_REWARD_POOL,
+];
 
 const PRECISION: u128 = 10_000;
+const BONUS_PRECISION: u128 = 1_000_000_000_000;
 
-pub fn get_months_elapsed(start_time: i64, current_time: i64) -> Result<u64> {
+// Days since 1970-01-01 to (year, month, day) of the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+// Calendar months keep the time of day and clamp to the end of shorter months (Jan 31 + 1 = Feb 28/29)
+pub fn add_months(timestamp: i64, months: u32, month_convention: MonthConvention) -> i64 {
+    match month_convention {
+        MonthConvention::ThirtyDays => timestamp + months as i64 * 30 * SECONDS_PER_DAY,
+        MonthConvention::Calendar => {
+            let (year, month, day) = civil_from_days(timestamp.div_euclid(SECONDS_PER_DAY));
+            let total_months = year * 12 + (month as i64 - 1) + months as i64;
+            let new_year = total_months.div_euclid(12);
+            let new_month = total_months.rem_euclid(12) as u32 + 1;
+            let new_day = day.min(days_in_month(new_year, new_month));
+
+            days_from_civil(new_year, new_month, new_day) * SECONDS_PER_DAY
+                + timestamp.rem_euclid(SECONDS_PER_DAY)
+        }
+    }
+}
+
+pub fn get_months_elapsed(
+    start_time: i64,
+    current_time: i64,
+    month_convention: MonthConvention,
+) -> Result<u64> {
     let seconds_elapsed = current_time
         .checked_sub(start_time)
         .ok_or(StakingError::Underflow)?;
-    Ok((seconds_elapsed / (30 * 24 * 60 * 60)) as u64)
+
+    match month_convention {
+        MonthConvention::ThirtyDays => Ok((seconds_elapsed / (30 * 24 * 60 * 60)) as u64),
+        MonthConvention::Calendar => {
+            let (start_year, start_month, _) = civil_from_days(start_time.div_euclid(SECONDS_PER_DAY));
+            let (year, month, _) = civil_from_days(current_time.div_euclid(SECONDS_PER_DAY));
+            let mut months = (year - start_year) * 12 + month as i64 - start_month as i64;
+            if months > 0 && add_months(start_time, months as u32, month_convention) > current_time {
+                months -= 1;
+            }
+
+            Ok(months.max(0) as u64)
+        }
+    }
 }
 
-pub fn get_week_number(current_time: i64, program_start: i64) -> Result<u64> {
+// Claim epochs start claim_epoch_offset seconds before program start, so epoch 0 may be shorter
+pub fn get_claim_epoch_number(staking_pool: &StakingPool, current_time: i64) -> Result<u64> {
     let seconds_elapsed = current_time
-        .checked_sub(program_start)
+        .checked_sub(staking_pool.program_start_time)
         .ok_or(StakingError::Underflow)?
-        .checked_add(SECONDS_PER_DAY) // for start from 21 (monday)
-        .ok_or(StakingError::Underflow)?;
-    Ok((seconds_elapsed / SECONDS_PER_WEEK) as u64)
+        .checked_add(staking_pool.claim_epoch_offset)
+        .ok_or(StakingError::Overflow)?;
+
+    match staking_pool.claim_epoch {
+        ClaimEpoch::Daily => Ok((seconds_elapsed / SECONDS_PER_DAY) as u64),
+        ClaimEpoch::Weekly => Ok((seconds_elapsed / SECONDS_PER_WEEK) as u64),
+        ClaimEpoch::Monthly => get_months_elapsed(
+            staking_pool.program_start_time - staking_pool.claim_epoch_offset,
+            current_time,
+            staking_pool.month_convention,
+        ),
+    }
+}
+
+// First day index of a claim epoch, epoch boundaries always fall on day boundaries
+pub fn get_claim_epoch_start_day(staking_pool: &StakingPool, epoch: u64) -> u64 {
+    let anchor_time = staking_pool.program_start_time - staking_pool.claim_epoch_offset;
+    let epoch_start = match staking_pool.claim_epoch {
+        ClaimEpoch::Daily => anchor_time + epoch as i64 * SECONDS_PER_DAY,
+        ClaimEpoch::Weekly => anchor_time + epoch as i64 * SECONDS_PER_WEEK,
+        ClaimEpoch::Monthly => add_months(anchor_time, epoch as u32, staking_pool.month_convention),
+    };
+
+    ((epoch_start - staking_pool.program_start_time).max(0) / SECONDS_PER_DAY) as u64
+}
+
+// The anchor offset is whole days and shorter than the epoch
+pub fn validate_claim_epoch(claim_epoch: ClaimEpoch, claim_epoch_offset: i64) -> Result<()> {
+    let max_offset = match claim_epoch {
+        ClaimEpoch::Daily => 0,
+        ClaimEpoch::Weekly => SECONDS_PER_WEEK - SECONDS_PER_DAY,
+        ClaimEpoch::Monthly => 27 * SECONDS_PER_DAY,
+    };
+    require!(
+        (0..=max_offset).contains(&claim_epoch_offset)
+            && claim_epoch_offset % SECONDS_PER_DAY == 0,
+        StakingError::InvalidClaimEpoch
+    );
+
+    Ok(())
 }
 
 pub fn get_day_index(current_time: i64, program_start: i64) -> Result<u64> {
@@ -40,27 +194,106 @@ pub fn get_day_index(current_time: i64, program_start: i64) -> Result<u64> {
     Ok(day)
 }
 
-pub fn get_stake_end_time(stake_entry: &StakeEntry) -> i64 {
-    stake_entry.start_time + (stake_entry.duration_months as i64 * 30 * SECONDS_PER_DAY)
+pub fn is_flexible_stake(stake_entry: &StakeEntry) -> bool {
+    stake_entry.duration_months == FLEXIBLE_DURATION_MONTHS
+}
+
+// Nominal lock length (30 day months), the voting power scale
+pub fn get_lock_period(stake_entry: &StakeEntry) -> i64 {
+    stake_entry.duration_months as i64 * 30 * SECONDS_PER_DAY
+}
+
+// Index of the lock period current_time falls in, auto-renewing stakes roll into a new period at maturity
+pub fn get_current_lock_period(
+    stake_entry: &StakeEntry,
+    month_convention: MonthConvention,
+    current_time: i64,
+) -> u32 {
+    if !stake_entry.auto_renew || is_flexible_stake(stake_entry) {
+        return stake_entry.renewal_count;
+    }
+
+    let months_elapsed = if current_time > stake_entry.start_time {
+        get_months_elapsed(stake_entry.start_time, current_time, month_convention).unwrap_or(0)
+    } else {
+        0
+    };
+    (months_elapsed / stake_entry.duration_months as u64) as u32
+}
+
+pub fn get_lock_start_time(
+    stake_entry: &StakeEntry,
+    month_convention: MonthConvention,
+    current_time: i64,
+) -> i64 {
+    let lock_period = get_current_lock_period(stake_entry, month_convention, current_time);
+    add_months(
+        stake_entry.start_time,
+        lock_period * stake_entry.duration_months as u32,
+        month_convention,
+    )
+}
+
+// End of the lock period current_time falls in, equals the stake end for non-renewing stakes
+pub fn get_lock_end_time(
+    stake_entry: &StakeEntry,
+    month_convention: MonthConvention,
+    current_time: i64,
+) -> i64 {
+    let lock_period = get_current_lock_period(stake_entry, month_convention, current_time);
+    add_months(
+        stake_entry.start_time,
+        (lock_period + 1) * stake_entry.duration_months as u32,
+        month_convention,
+    )
+}
+
+// None for flexible and auto-renewing stakes, they have no lock end
+pub fn get_stake_end_time(stake_entry: &StakeEntry, month_convention: MonthConvention) -> Option<i64> {
+    if is_flexible_stake(stake_entry) || stake_entry.auto_renew {
+        return None;
+    }
+
+    Some(add_months(
+        stake_entry.start_time,
+        (stake_entry.renewal_count + 1) * stake_entry.duration_months as u32,
+        month_convention,
+    ))
+}
+
+// The pool moves a stake to total_matured at the week boundary after its lock end, accrual
+// switches to the matured weight at the same time
+pub fn get_matured_time(stake_entry: &StakeEntry, staking_pool: &StakingPool) -> Option<i64> {
+    get_voting_end_week(staking_pool, stake_entry).map(|week| get_week_boundary(staking_pool, week))
+}
+
+// Days from the matured time on, when the stake earns at the matured weight (if any)
+pub fn is_matured_day(day: u64, stake_entry: &StakeEntry, staking_pool: &StakingPool) -> bool {
+    let day_timestamp = staking_pool.program_start_time + (day as i64 * SECONDS_PER_DAY);
+
+    get_matured_time(stake_entry, staking_pool).is_some_and(|matured_time| day_timestamp >= matured_time)
 }
 
 pub fn should_skip_day(
     day: u64,
     stake_entry: &StakeEntry,
-    program_start: i64,
+    staking_pool: &StakingPool,
     is_unstaking: bool,
-    current_time: i64
+    current_time: i64,
 ) -> Result<bool> {
     // Convert day index to timestamp
-    let day_timestamp = program_start + (day as i64 * SECONDS_PER_DAY);
+    let day_timestamp = staking_pool.program_start_time + (day as i64 * SECONDS_PER_DAY);
     
     // Skip days before stake start
     if day_timestamp < stake_entry.start_time {
         return Ok(true);
     }
     
-    // Skip days after stake end time
-    let stake_end_time = get_stake_end_time(stake_entry);
+    // Skip days after the stake matured, flexible stakes (and matured ones when the pool pays a
+    // matured weight) accrue until they are unstaked
+    let stake_end_time = get_matured_time(stake_entry, staking_pool)
+        .filter(|_| get_accrual_weights(staking_pool, day).matured_weight == 0)
+        .unwrap_or(i64::MAX);
     if day_timestamp >= stake_end_time {
         return Ok(true);
     }
@@ -96,239 +329,2144 @@ pub fn calculate_base_apy(total_staked: u64, available_rewards: u64) -> Result<u
     Ok(apy as u64)
 }
 
+pub fn get_released_rewards(staking_pool: &StakingPool, current_time: i64) -> Result<u64> {
+    let months_elapsed = get_months_elapsed(
+        staking_pool.program_start_time,
+        current_time,
+        staking_pool.month_convention,
+    )?;
+
+    let month = (months_elapsed as usize).min(EMISSION_MONTHS - 1);
+
+    Ok(staking_pool.emission_schedule[month])
+}
+
+pub fn get_total_emission(staking_pool: &StakingPool) -> u64 {
+    staking_pool.emission_schedule[EMISSION_MONTHS - 1]
+}
+
 pub fn get_available_rewards(staking_pool: &StakingPool, current_time: i64) -> Result<u64> {
-    let months_elapsed = get_months_elapsed(staking_pool.program_start_time, current_time)?;
-
-    let available_pool = match months_elapsed {
-        0 => 20_833_333_000_000_000,
-        1 => 41_666_667_000_000_000,
-        2 => 62_500_000_000_000_000,
-        3 => 83_333_333_000_000_000,
-        4 => 104_166_667_000_000_000,
-        5 => 125_000_000_000_000_000,
-        6 => 145_833_333_000_000_000,
-        7 => 166_666_667_000_000_000,
-        8 => 187_500_000_000_000_000,
-        9 => 208_333_333_000_000_000,
-        10 => 229_166_667_000_000_000,
-        _ => TOTAL_REWARD_POOL,
-    };
+    let released = get_released_rewards(staking_pool, current_time)?;
+
+    // Never release more than was actually deposited via fund_reward_vault
+    let available_pool = released.min(staking_pool.total_funded);
+
+    Ok(available_pool.saturating_sub(staking_pool.total_rewards_distributed))
+}
+
+// Rewards the vault has to keep: the part of the schedule that is not released yet
+// plus everything already released but not paid out (accrued liabilities)
+pub fn get_reserved_rewards(staking_pool: &StakingPool, current_time: i64) -> Result<u64> {
+    let released = get_released_rewards(staking_pool, current_time)?;
+
+    let future_schedule = get_total_emission(staking_pool)
+        .checked_sub(released)
+        .ok_or(StakingError::Underflow)?;
+    let accrued_liabilities = released.saturating_sub(staking_pool.total_rewards_distributed);
+
+    future_schedule
+        .checked_add(accrued_liabilities)
+        .ok_or(StakingError::Overflow)?
+        .checked_add(staking_pool.bonus_pot)
+        .ok_or(StakingError::Overflow)?
+        .checked_add(staking_pool.referral_pending)
+        .ok_or(StakingError::Overflow)?
+        .checked_add(staking_pool.crank_budget)
+        .ok_or(StakingError::Overflow.into())
+}
+
+pub fn get_excess_rewards(
+    staking_pool: &StakingPool,
+    vault_balance: u64,
+    current_time: i64,
+) -> Result<u64> {
+    let reserved = get_reserved_rewards(staking_pool, current_time)?;
+    Ok(vault_balance.saturating_sub(reserved))
+}
 
-    available_pool
-        .checked_sub(staking_pool.total_rewards_distributed)
-        .ok_or(StakingError::Underflow.into())
+// Stake the APY is spread over, matured stakes are left out unless they still earn
+pub fn get_earning_stake(staking_pool: &StakingPool) -> u64 {
+    if staking_pool.matured_weight > 0 {
+        staking_pool.total_staked
+    } else {
+        staking_pool
+            .total_staked
+            .saturating_sub(staking_pool.total_matured)
+    }
 }
 
 pub fn update_daily_rate(staking_pool: &mut StakingPool, current_time: i64) -> Result<()> {
     let day_index = get_day_index(current_time, staking_pool.program_start_time)?;
 
+    // Moves stakes that reached their lock end to the matured bucket
+    checkpoint_voting_power(staking_pool, current_time)?;
+
+    // Today's rate is computed only after all earlier days are final
+    if day_index > staking_pool.rate_snapshot.day_index
+        && !finalize_daily_rates(staking_pool, day_index, current_time)?
+    {
+        return Ok(());
+    }
+
     // Always update the rate when called
     let available_rewards = get_available_rewards(staking_pool, current_time)?;
+    let earning_stake = get_earning_stake(staking_pool);
 
     // Calculate base APY without duration weights
-    let base_apy = calculate_base_apy(staking_pool.total_staked, available_rewards)?;
+    let base_apy = calculate_base_apy(earning_stake, available_rewards)?;
+    let smoothed_apy = get_smoothed_daily_rate(staking_pool, day_index, base_apy);
 
     if staking_pool.daily_rates.len() <= day_index as usize {
         staking_pool.daily_rates.resize(day_index as usize + 1, 0);
     }
+    if staking_pool.raw_daily_rates.len() <= day_index as usize {
+        staking_pool.raw_daily_rates.resize(day_index as usize + 1, 0);
+    }
 
-    staking_pool.daily_rates[day_index as usize] = base_apy;
+    staking_pool.daily_rates[day_index as usize] = smoothed_apy;
+    staking_pool.raw_daily_rates[day_index as usize] = base_apy;
+    staking_pool.rate_snapshot = RateSnapshot {
+        day_index,
+        total_staked: staking_pool.total_staked,
+        earning_stake,
+        available_rewards,
+    };
     staking_pool.last_update_time = current_time;
 
     Ok(())
 }
 
-pub fn calculate_daily_reward(
-    stake_amount: u64,
-    daily_rate: u64,
-    duration_months: u8,
-    normalization_k: u128,
-) -> Result<u64> {
-    // msg!("Calculating daily reward for stake_amount: {}, daily_rate: {}, duration_months: {}, normalization_k: {}", stake_amount, daily_rate, duration_months, normalization_k);    
-    // Apply weight multiplier based on stake duration
-    // NORMALIZATION_K in original was 500 but we use 5000 for better precision
-    // and can use 10 as a multiplier for 1.0x, 1.5x, etc.
-    let weight_multiplier = match duration_months {
-        3 => 10,  // 1.0x
-        6 => 15,  // 1.5x
-        9 => 20,  // 2.0x
-        12 => 30, // 3.0x
-        _ => return Err(StakingError::InvalidDuration.into()),
-    };
-    // msg!("weight_multiplier: {}", weight_multiplier);
+// Finalizes the latest computed day and fills the days after it that had no rate update
+// with the gap fill rate, at most MAX_BACKFILL_DAYS per call.
+// Returns false while days before today are still left for later calls
+fn finalize_daily_rates(staking_pool: &mut StakingPool, day_index: u64, current_time: i64) -> Result<bool> {
+    let gap_rate = get_gap_fill_rate(staking_pool);
+    let mut filled_days = 0;
 
-    let weight_factor = (weight_multiplier as u128)
-        .checked_mul(PRECISION as u128)
-        .ok_or(StakingError::Overflow)?
-        .checked_div(normalization_k)
-        .ok_or(StakingError::DivisionByZero)?;
+    loop {
+        let next_day = staking_pool.rate_snapshot.day_index + 1;
+        if next_day < day_index && filled_days == MAX_BACKFILL_DAYS {
+            return Ok(false);
+        }
 
-    // msg!("weight_factor: {}", weight_factor);
+        emit_daily_rate_finalized(staking_pool, current_time)?;
+        if next_day == day_index {
+            return Ok(true);
+        }
 
-    let mut daily_rate_with_weight = (daily_rate as u128)
-        .checked_mul(weight_factor as u128)
-        .ok_or(StakingError::Overflow)?
-        .checked_mul(PRECISION as u128)
-        .ok_or(StakingError::Overflow)?;
+        // No rate was computed for a backfilled day, so it has no raw rate
+        staking_pool.daily_rates[next_day as usize] = gap_rate;
+        staking_pool.raw_daily_rates[next_day as usize] = 0;
+        staking_pool.rate_snapshot.day_index = next_day;
+        filled_days += 1;
+    }
+}
 
-    // msg!("daily_rate_with_weight: {}", daily_rate_with_weight);
+fn emit_daily_rate_finalized(staking_pool: &StakingPool, current_time: i64) -> Result<()> {
+    let snapshot = staking_pool.rate_snapshot;
+    let rate = get_daily_rate(staking_pool, snapshot.day_index);
 
-    let max_daily_rate = 10 * PRECISION * PRECISION * PRECISION; // 100% APY with precision
-    if daily_rate_with_weight > max_daily_rate {
-        daily_rate_with_weight = max_daily_rate
+    emit!(DailyRateFinalizedEvent {
+        day_index: snapshot.day_index,
+        rate,
+        computed_rate: get_computed_daily_rate(staking_pool, snapshot.day_index),
+        raw_rate: get_raw_daily_rate(staking_pool, snapshot.day_index),
+        total_staked: snapshot.total_staked,
+        earning_stake: snapshot.earning_stake,
+        available_rewards: snapshot.available_rewards,
+        normalization_k: staking_pool.normalization_k,
+        tier_apy_bps: get_tier_apy_bps(staking_pool, rate)?,
+        timestamp: current_time,
+    });
+
+    Ok(())
+}
+
+// Tip of a crank_daily_rate call, only calls that finalized days are paid and never more than the budget
+pub fn get_crank_tip(staking_pool: &StakingPool, last_computed_day: u64) -> u64 {
+    if staking_pool.rate_snapshot.day_index > last_computed_day {
+        staking_pool.crank_tip.min(staking_pool.crank_budget)
+    } else {
+        0
     }
-    // msg!("daily_rate_with_weight after: {}", daily_rate_with_weight);
+}
 
-    let daily_reward = (stake_amount as u128)
-        .checked_mul(daily_rate_with_weight as u128)
-        .ok_or(StakingError::Overflow)?
-        .checked_div(360)
-        .ok_or(StakingError::DivisionByZero)?;
+// Index of the duration in tier_staked and the per tier caps
+pub fn get_tier_slot(duration_months: u8) -> Result<usize> {
+    match duration_months {
+        3 => Ok(0),
+        6 => Ok(1),
+        9 => Ok(2),
+        12 => Ok(3),
+        FLEXIBLE_DURATION_MONTHS => Ok(TIER_COUNT),
+        _ => Err(StakingError::InvalidDuration.into()),
+    }
+}
 
-    // msg!("daily reward 1 {}", daily_reward);
+pub fn get_weight_multiplier(staking_pool: &StakingPool, duration_months: u8) -> Result<u64> {
+    get_tier_weight(&get_current_weights(staking_pool), duration_months)
+}
 
-    let daily_reward_final = (daily_reward as u128)
-        .checked_div(PRECISION)
-        .ok_or(StakingError::DivisionByZero)?
-        .checked_div(PRECISION)
-        .ok_or(StakingError::DivisionByZero)?
-        .checked_div(PRECISION)
-        .ok_or(StakingError::DivisionByZero)?;
+pub fn get_tier_weight(weights: &AccrualWeights, duration_months: u8) -> Result<u64> {
+    // 10 is used as a multiplier for 1.0x, 1.5x, etc.
+    let tier = get_tier_slot(duration_months)?;
+    if tier == TIER_COUNT {
+        return Ok(weights.flexible_weight as u64);
+    }
 
-    // msg!("Calculated daily reward: {}", daily_reward_final);
+    Ok(weights.tier_weights[tier] as u64)
+}
 
-    Ok(daily_reward_final as u64)
+pub fn get_current_weights(staking_pool: &StakingPool) -> AccrualWeights {
+    AccrualWeights {
+        until_day: u64::MAX,
+        tier_weights: staking_pool.tier_weights,
+        flexible_weight: staking_pool.flexible_weight,
+        matured_weight: staking_pool.matured_weight,
+        normalization_k: staking_pool.normalization_k,
+    }
 }
 
-pub fn calculate_claimable_rewards(
-    stake_entry: &StakeEntry,
-    staking_pool: &StakingPool,
-    current_time: i64,
-    is_unstaking: bool,
-) -> Result<u64> {
-    let current_week = get_week_number(current_time, staking_pool.program_start_time)?;
-    let last_claimed_week = stake_entry.last_claim_week;
-    
-    // Can only claim up to previous week (not current week) when unstaking
-    let claimable_up_to_week = if is_unstaking {
-        current_week.saturating_sub(1)
-    } else {
-        current_week
-    };
+// Weights a day accrues with, a weight change applies from the day after its execution
+pub fn get_accrual_weights(staking_pool: &StakingPool, day: u64) -> AccrualWeights {
+    staking_pool
+        .weight_history
+        .iter()
+        .find(|weights| day < weights.until_day)
+        .copied()
+        .unwrap_or_else(|| get_current_weights(staking_pool))
+}
 
-    if claimable_up_to_week <= last_claimed_week {
-        return Ok(0);
+// Keeps the current weights for the days up to today, called before a weight change is applied
+pub fn record_weight_change(staking_pool: &mut StakingPool, current_time: i64) -> Result<()> {
+    // Nothing has accrued before program start
+    if current_time < staking_pool.program_start_time {
+        return Ok(());
     }
 
-    // Calculate day range
-    let start_day = (last_claimed_week * 7) as usize;
-    let end_day = (claimable_up_to_week * 7) as usize;
+    let until_day = ((current_time - staking_pool.program_start_time) / SECONDS_PER_DAY) as u64 + 1;
+    // An earlier change today already kept today's weights
+    if staking_pool
+        .weight_history
+        .last()
+        .is_some_and(|weights| weights.until_day == until_day)
+    {
+        return Ok(());
+    }
 
-    let mut total_rewards = 0u64;
-    let mut last_daily_rate = 0u64;
-    
-    // Sum rewards for each day
-    for day in start_day..end_day.min(MAX_DAILY_RATES) {
-        // Check if we should skip this day
-        if should_skip_day(
-            day as u64, 
-            stake_entry, 
-            staking_pool.program_start_time, 
-            is_unstaking, 
-            current_time
-        )? {
-            continue;
-        }
-        
-        let daily_rate = if day < staking_pool.daily_rates.len() {
-            staking_pool.daily_rates[day]
-        } else {
-            last_daily_rate
-        };
-        
-        if daily_rate > 0 {
-            last_daily_rate = daily_rate;
-        }
+    require!(
+        staking_pool.weight_history.len() < MAX_WEIGHT_CHANGES,
+        StakingError::TooManyWeightChanges
+    );
+    let weights = AccrualWeights {
+        until_day,
+        ..get_current_weights(staking_pool)
+    };
+    staking_pool.weight_history.push(weights);
 
-        let daily_reward = calculate_daily_reward(
-            stake_entry.amount,
-            last_daily_rate,
-            stake_entry.duration_months,
-            staking_pool.normalization_k,
-        )?;
+    Ok(())
+}
 
-        total_rewards = total_rewards
-            .checked_add(daily_reward)
-            .ok_or(StakingError::Overflow)?;
+// Room left under a cap, u64::MAX when the cap is disabled (0)
+fn get_cap_remaining(cap: u64, used: u64) -> u64 {
+    if cap == 0 {
+        u64::MAX
+    } else {
+        cap.saturating_sub(used)
     }
-
-    Ok(total_rewards)
 }
 
-pub fn calculate_total_rewards_for_claim_all<'info>(
-    remaining_accounts: &[AccountInfo<'info>],
-    user: &Pubkey,
-    staking_pool: &StakingPool,
-    staking_pool_key: &Pubkey,
-    program_id: &Pubkey,
-    current_time: i64,
-) -> Result<(u64, Vec<usize>)> {
-    let mut total_rewards = 0u64;
-    let mut valid_stake_indices = Vec::new();
-    // msg!("Remaining accounts count: {}", remaining_accounts.len());
+// Stake counted against a tier cap, the liquid stake is weighted as the flexible tier
+fn get_tier_staked(staking_pool: &StakingPool, tier: usize) -> u64 {
+    let liquid_staked = if tier == TIER_COUNT {
+        staking_pool.liquid_staked
+    } else {
+        0
+    };
 
-    // Iterate through all passed StakeEntry accounts
-    for (index, stake_entry_account_info) in remaining_accounts.iter().enumerate() {
-        // msg!("Processing stake entry at index: {}", index);
-        // 1. Verify that PDA is correct
-        let index_bytes = (index as u64).to_le_bytes();
+    staking_pool.tier_staked[tier].saturating_add(liquid_staked)
+}
 
-        let (expected_pda, _bump) = Pubkey::find_program_address(
-            &[
-                b"stake_entry",
-                user.as_ref(),
-                staking_pool_key.as_ref(),
-                &index_bytes,
-            ],
-            program_id,
-        );
+// UserStakingAccount PDAs exist once the wallet has staked, 0 before that
+pub fn get_user_total_staked(user_staking_account: &AccountInfo) -> Result<u64> {
+    if user_staking_account.data_is_empty() {
+        return Ok(0);
+    }
 
-        require!(
-            stake_entry_account_info.key() == expected_pda,
-            StakingError::InvalidStakeIndex
-        );
+    let data = user_staking_account.try_borrow_data()?;
+    Ok(UserStakingAccount::try_deserialize(&mut &data[..])?.total_staked)
+}
 
-        // 2. Deserialize StakeEntry
-        let stake_entry_data = stake_entry_account_info.try_borrow_data()?;
-        let stake_entry = StakeEntry::try_deserialize(&mut stake_entry_data.as_ref())?;
+// Stake tokens backing a receipt balance at the current exchange rate
+pub fn get_liquid_value(receipt_amount: u64, exchange_rate: u128) -> Result<u64> {
+    let value = (receipt_amount as u128)
+        .checked_mul(exchange_rate)
+        .ok_or(StakingError::Overflow)?
+        .checked_div(EXCHANGE_RATE_PRECISION)
+        .ok_or(StakingError::DivisionByZero)?;
 
-        // 3. Skip inactive stakes
-        if !stake_entry.is_active {
-            continue;
-        }
+    Ok(value.min(u64::MAX as u128) as u64)
+}
 
-        // 4. Verify owner
-        require!(stake_entry.owner == *user, StakingError::Unauthorized);
+pub fn validate_stake_caps(
+    staking_pool: &StakingPool,
+    user_total_staked: u64,
+    duration_months: u8,
+    amount: u64,
+) -> Result<()> {
+    let caps = &staking_pool.staking_caps;
+    let tier = get_tier_slot(duration_months)?;
 
-        // 5. Calculate rewards for this stake
-        let rewards = calculate_claimable_rewards(
-            &stake_entry, 
-            staking_pool, 
-            current_time, 
-            false,
-        )?;
-        // msg!("Calculated rewards for stake {}: {}", index, rewards);
+    require!(amount >= caps.min_stake_amount, StakingError::BelowMinimumStake);
+    require!(
+        amount <= get_cap_remaining(caps.max_stake_per_user, user_total_staked),
+        StakingError::UserStakeCapExceeded
+    );
+    require!(
+        amount <= get_cap_remaining(caps.max_stake_per_tier[tier], get_tier_staked(staking_pool, tier)),
+        StakingError::TierCapExceeded
+    );
+    require!(
+        amount <= get_cap_remaining(caps.pool_cap, staking_pool.total_staked),
+        StakingError::PoolCapExceeded
+    );
 
-        if rewards > 0 {
-            // 6. Accumulate total amount
-            total_rewards = total_rewards
-                .checked_add(rewards)
-                .ok_or(StakingError::Overflow)?;
+    Ok(())
+}
 
-            // 7. Track indices of stakes with rewards
-            valid_stake_indices.push(index);
-        }
+pub fn get_staking_capacity(staking_pool: &StakingPool, user_total_staked: u64) -> StakingCapacity {
+    let caps = &staking_pool.staking_caps;
+    let mut tier_remaining = [0u64; TIER_SLOTS];
+    for (tier, remaining) in tier_remaining.iter_mut().enumerate() {
+        *remaining = get_cap_remaining(caps.max_stake_per_tier[tier], get_tier_staked(staking_pool, tier));
     }
 
-    Ok((total_rewards, valid_stake_indices))
+    StakingCapacity {
+        min_stake_amount: caps.min_stake_amount,
+        user_remaining: get_cap_remaining(caps.max_stake_per_user, user_total_staked),
+        tier_remaining,
+        pool_remaining: get_cap_remaining(caps.pool_cap, staking_pool.total_staked),
+    }
+}
+
+// Deny-list entries are PDAs that exist only for denied wallets
+pub fn validate_not_denied(denylist_entry: &AccountInfo) -> Result<()> {
+    require!(denylist_entry.data_is_empty(), StakingError::WalletDenied);
+
+    Ok(())
+}
+
+// Leaves are sha256(wallet), pairs are hashed in sorted order
+pub fn verify_merkle_proof(proof: &[[u8; 32]], root: &[u8; 32], wallet: &Pubkey) -> bool {
+    let mut node = hashv(&[wallet.as_ref()]).to_bytes();
+    for sibling in proof {
+        node = if node <= *sibling {
+            hashv(&[&node, sibling]).to_bytes()
+        } else {
+            hashv(&[sibling, &node]).to_bytes()
+        };
+    }
+
+    node == *root
+}
+
+// With the allow-list enabled the wallet needs an entry created by the gatekeeper
+// or a proof against the pool's Merkle root
+pub fn validate_stake_access(
+    staking_pool: &StakingPool,
+    wallet: &Pubkey,
+    allowlist_entry: Option<&AllowlistEntry>,
+    denylist_entry: &AccountInfo,
+    proof: &[[u8; 32]],
+) -> Result<()> {
+    validate_not_denied(denylist_entry)?;
+    if !staking_pool.allowlist_enabled {
+        return Ok(());
+    }
+    if allowlist_entry.is_some_and(|entry| entry.wallet == *wallet) {
+        return Ok(());
+    }
+
+    require!(
+        staking_pool.allowlist_root != [0; 32]
+            && verify_merkle_proof(proof, &staking_pool.allowlist_root, wallet),
+        StakingError::NotAllowlisted
+    );
+
+    Ok(())
+}
+
+// Rate of days after the latest computed one, until they are backfilled with it
+pub fn get_gap_fill_rate(staking_pool: &StakingPool) -> u64 {
+    match staking_pool.gap_fill_mode {
+        GapFillMode::CarryForward => {
+            get_computed_daily_rate(staking_pool, staking_pool.rate_snapshot.day_index)
+        }
+        GapFillMode::Zero => 0,
+    }
+}
+
+// Rate rewards accrue at on a day: the override, the computed rate or, for days
+// without a rate yet, the gap fill rate
+pub fn get_accrual_rate(staking_pool: &StakingPool, day: u64) -> u64 {
+    if let Some(rate_override) = staking_pool
+        .daily_rate_overrides
+        .iter()
+        .find(|rate_override| rate_override.day_index == day)
+    {
+        return rate_override.rate;
+    }
+
+    if day > staking_pool.rate_snapshot.day_index {
+        get_gap_fill_rate(staking_pool)
+    } else {
+        get_computed_daily_rate(staking_pool, day)
+    }
+}
+
+pub fn get_computed_daily_rate(staking_pool: &StakingPool, day: u64) -> u64 {
+    staking_pool
+        .daily_rates
+        .get(day as usize)
+        .copied()
+        .unwrap_or(0)
+}
+
+pub fn get_raw_daily_rate(staking_pool: &StakingPool, day: u64) -> u64 {
+    staking_pool
+        .raw_daily_rates
+        .get(day as usize)
+        .copied()
+        .unwrap_or(0)
+}
+
+// Applies rate_smoothing to the raw rate of the day. The previous day's rate is the
+// reference for the EMA and the daily change limit, without one (day 0 or a zero rate)
+// only the bounds apply
+pub fn get_smoothed_daily_rate(staking_pool: &StakingPool, day: u64, raw_rate: u64) -> u64 {
+    let smoothing = staking_pool.rate_smoothing;
+    let previous = if day > 0 {
+        get_computed_daily_rate(staking_pool, day - 1)
+    } else {
+        0
+    };
+    let mut rate = raw_rate;
+
+    if previous > 0 {
+        if smoothing.mode == SmoothingMode::Ema {
+            // previous + (raw - previous) * 2 / (ema_days + 1)
+            let ema_days = smoothing.ema_days as u128;
+            rate = ((raw_rate as u128 * 2 + previous as u128 * (ema_days - 1)) / (ema_days + 1))
+                as u64;
+        }
+        if smoothing.max_daily_change_bps > 0 {
+            let max_change = (previous as u128 * smoothing.max_daily_change_bps as u128
+                / BASIS_POINTS as u128) as u64;
+            rate = rate.clamp(
+                previous.saturating_sub(max_change),
+                previous.saturating_add(max_change),
+            );
+        }
+    }
+    if smoothing.min_rate > 0 {
+        rate = rate.max(smoothing.min_rate);
+    }
+    if smoothing.max_rate > 0 {
+        rate = rate.min(smoothing.max_rate);
+    }
+
+    rate
+}
+
+// Authority override if there is one, otherwise the computed rate
+pub fn get_daily_rate(staking_pool: &StakingPool, day: u64) -> u64 {
+    staking_pool
+        .daily_rate_overrides
+        .iter()
+        .find(|rate_override| rate_override.day_index == day)
+        .map(|rate_override| rate_override.rate)
+        .unwrap_or_else(|| get_computed_daily_rate(staking_pool, day))
+}
+
+// Only future days can be overridden: past days may already be claimed and the current
+// day is already paid out to ProRata unstakes
+pub fn validate_daily_rate_override(
+    staking_pool: &StakingPool,
+    day_index: u64,
+    current_time: i64,
+) -> Result<()> {
+    let current_day = get_day_index(current_time, staking_pool.program_start_time)?;
+    require!(day_index > current_day, StakingError::RetroactiveRateChange);
+
+    Ok(())
+}
+
+pub fn set_daily_rate_override(
+    staking_pool: &mut StakingPool,
+    day_index: u64,
+    rate: u64,
+) -> Result<()> {
+    match staking_pool
+        .daily_rate_overrides
+        .iter_mut()
+        .find(|rate_override| rate_override.day_index == day_index)
+    {
+        Some(rate_override) => rate_override.rate = rate,
+        None => {
+            require!(
+                staking_pool.daily_rate_overrides.len() < MAX_RATE_OVERRIDES,
+                StakingError::TooManyRateOverrides
+            );
+            staking_pool
+                .daily_rate_overrides
+                .push(DailyRateOverride { day_index, rate });
+        }
+    }
+
+    Ok(())
+}
+
+pub fn calculate_daily_reward(
+    stake_amount: u64,
+    daily_rate: u64,
+    weight_multiplier: u64,
+    boost_bps: u16,
+    normalization_k: u128,
+) -> Result<u64> {
+    // msg!("Calculating daily reward for stake_amount: {}, daily_rate: {}, weight_multiplier: {}, normalization_k: {}", stake_amount, daily_rate, weight_multiplier, normalization_k);
+    let daily_rate_with_weight =
+        get_weighted_daily_rate(daily_rate, weight_multiplier, boost_bps, normalization_k)?;
+
+    let daily_reward = (stake_amount as u128)
+        .checked_mul(daily_rate_with_weight as u128)
+        .ok_or(StakingError::Overflow)?
+        .checked_div(360)
+        .ok_or(StakingError::DivisionByZero)?;
+
+    // msg!("daily reward 1 {}", daily_reward);
+
+    let daily_reward_final = (daily_reward as u128)
+        .checked_div(PRECISION)
+        .ok_or(StakingError::DivisionByZero)?
+        .checked_div(PRECISION)
+        .ok_or(StakingError::DivisionByZero)?
+        .checked_div(PRECISION)
+        .ok_or(StakingError::DivisionByZero)?;
+
+    // msg!("Calculated daily reward: {}", daily_reward_final);
+
+    Ok(daily_reward_final as u64)
+}
+
+// Yearly rate of a tier scaled by PRECISION^3, capped
+fn get_weighted_daily_rate(
+    daily_rate: u64,
+    weight_multiplier: u64,
+    boost_bps: u16,
+    normalization_k: u128,
+) -> Result<u128> {
+    // NORMALIZATION_K in original was 500 but we use 5000 for better precision
+
+    // Boost raises the weight itself, so the max daily rate cap still applies
+    #[allow(clippy::unnecessary_cast)]
+    let weight_factor = (weight_multiplier as u128)
+        .checked_mul(PRECISION as u128)
+        .ok_or(StakingError::Overflow)?
+        .checked_mul(BASIS_POINTS as u128 + boost_bps as u128)
+        .ok_or(StakingError::Overflow)?
+        .checked_div(BASIS_POINTS as u128)
+        .ok_or(StakingError::DivisionByZero)?
+        .checked_div(normalization_k)
+        .ok_or(StakingError::DivisionByZero)?;
+
+    // msg!("weight_factor: {}", weight_factor);
+
+    #[allow(clippy::unnecessary_cast)]
+    let mut daily_rate_with_weight = (daily_rate as u128)
+        .checked_mul(weight_factor as u128)
+        .ok_or(StakingError::Overflow)?
+        .checked_mul(PRECISION as u128)
+        .ok_or(StakingError::Overflow)?;
+
+    // msg!("daily_rate_with_weight: {}", daily_rate_with_weight);
+
+    let max_daily_rate = 10 * PRECISION * PRECISION * PRECISION; // 100% APY with precision
+    if daily_rate_with_weight > max_daily_rate {
+        daily_rate_with_weight = max_daily_rate
+    }
+    // msg!("daily_rate_with_weight after: {}", daily_rate_with_weight);
+
+    Ok(daily_rate_with_weight)
+}
+
+// What an unboosted stake of each tier earns over 360 days at the rate, in basis points
+pub fn get_tier_apy_bps(staking_pool: &StakingPool, daily_rate: u64) -> Result<[u64; TIER_SLOTS]> {
+    let mut tier_apy_bps = [0u64; TIER_SLOTS];
+    for (apy_bps, duration_months) in tier_apy_bps.iter_mut().zip(TIER_DURATIONS) {
+        let weighted_rate = get_weighted_daily_rate(
+            daily_rate,
+            get_weight_multiplier(staking_pool, duration_months)?,
+            0,
+            staking_pool.normalization_k,
+        )?;
+        *apy_bps = weighted_rate
+            .checked_mul(BASIS_POINTS as u128)
+            .ok_or(StakingError::Overflow)?
+            .checked_div(PRECISION * PRECISION * PRECISION)
+            .ok_or(StakingError::DivisionByZero)? as u64;
+    }
+
+    Ok(tier_apy_bps)
+}
+
+// Stake weight used for the bonus pot, fixed at stake time
+pub fn calculate_weighted_amount(
+    staking_pool: &StakingPool,
+    amount: u64,
+    duration_months: u8,
+) -> Result<u128> {
+    let weight_multiplier = get_weight_multiplier(staking_pool, duration_months)?;
+
+    (amount as u128)
+        .checked_mul(weight_multiplier as u128)
+        .ok_or(StakingError::Overflow.into())
+}
+
+pub fn get_bonus_debt(staking_pool: &StakingPool, weighted_amount: u128) -> Result<u128> {
+    weighted_amount
+        .checked_mul(staking_pool.bonus_per_weight)
+        .ok_or(StakingError::Overflow)?
+        .checked_div(BONUS_PRECISION)
+        .ok_or(StakingError::DivisionByZero.into())
+}
+
+pub fn calculate_pending_bonus(stake_entry: &StakeEntry, staking_pool: &StakingPool) -> Result<u64> {
+    let accumulated = get_bonus_debt(staking_pool, stake_entry.weighted_amount)?;
+    let pending = accumulated.saturating_sub(stake_entry.bonus_debt);
+
+    u64::try_from(pending).map_err(|_| StakingError::Overflow.into())
+}
+
+// Adds a redistributed penalty to the bonus pot of all currently weighted stakes
+pub fn distribute_bonus(staking_pool: &mut StakingPool, amount: u64) -> Result<()> {
+    require!(
+        staking_pool.total_weighted_stake > 0,
+        StakingError::DivisionByZero
+    );
+
+    let increment = (amount as u128)
+        .checked_mul(BONUS_PRECISION)
+        .ok_or(StakingError::Overflow)?
+        .checked_div(staking_pool.total_weighted_stake)
+        .ok_or(StakingError::DivisionByZero)?;
+
+    staking_pool.bonus_per_weight = staking_pool
+        .bonus_per_weight
+        .checked_add(increment)
+        .ok_or(StakingError::Overflow)?;
+    staking_pool.bonus_pot = staking_pool
+        .bonus_pot
+        .checked_add(amount)
+        .ok_or(StakingError::Overflow)?;
+
+    Ok(())
+}
+
+// Sums daily rewards for days in [start_day, end_day)
+pub fn calculate_rewards_for_days(
+    stake_entry: &StakeEntry,
+    staking_pool: &StakingPool,
+    boost_bps: u16,
+    start_day: u64,
+    end_day: u64,
+    current_time: i64,
+    is_unstaking: bool,
+) -> Result<u64> {
+    let mut total_rewards = 0u64;
+    
+    // Sum rewards for each day
+    for day in start_day as usize..(end_day as usize).min(MAX_DAILY_RATES) {
+        let weights = get_accrual_weights(staking_pool, day as u64);
+
+        // Seconds of the day earned at the tier weight and at the matured weight
+        let (locked_seconds, matured_seconds) = if staking_pool.accrual_mode == AccrualMode::ProRata {
+            let (locked_seconds, matured_seconds) =
+                get_pro_rata_seconds(day as u64, stake_entry, staking_pool, is_unstaking, current_time);
+            if locked_seconds + matured_seconds == 0 {
+                continue;
+            }
+            (locked_seconds, matured_seconds)
+        } else {
+            // Check if we should skip this day
+            if should_skip_day(
+                day as u64, 
+                stake_entry, 
+                staking_pool, 
+                is_unstaking, 
+                current_time
+            )? {
+                continue;
+            }
+
+            if is_matured_day(day as u64, stake_entry, staking_pool) {
+                (0, SECONDS_PER_DAY)
+            } else {
+                (SECONDS_PER_DAY, 0)
+            }
+        };
+        
+        let daily_rate = get_accrual_rate(staking_pool, day as u64);
+
+        for (day_weight, seconds) in [
+            (get_tier_weight(&weights, stake_entry.duration_months)?, locked_seconds),
+            (weights.matured_weight as u64, matured_seconds),
+        ] {
+            if day_weight == 0 || seconds == 0 {
+                continue;
+            }
+
+            let daily_reward = calculate_daily_reward(
+                stake_entry.amount,
+                daily_rate,
+                day_weight,
+                boost_bps,
+                weights.normalization_k,
+            )?;
+            let reward = (daily_reward as u128)
+                .checked_mul(seconds as u128)
+                .ok_or(StakingError::Overflow)?
+                / SECONDS_PER_DAY as u128;
+
+            total_rewards = total_rewards
+                .checked_add(reward as u64)
+                .ok_or(StakingError::Overflow)?;
+        }
+    }
+
+    Ok(total_rewards)
+}
+
+// Pro-rata accrual: the part of the day the stake was staked, split at the matured time into
+// (locked, matured) seconds. The day of the stake start and, when unstaking, the current day are partial
+pub fn get_pro_rata_seconds(
+    day: u64,
+    stake_entry: &StakeEntry,
+    staking_pool: &StakingPool,
+    is_unstaking: bool,
+    current_time: i64,
+) -> (i64, i64) {
+    let day_start = staking_pool.program_start_time + (day as i64 * SECONDS_PER_DAY);
+    let day_end = day_start + SECONDS_PER_DAY;
+
+    let from = day_start.max(stake_entry.start_time);
+    let to = if is_unstaking {
+        day_end.min(current_time)
+    } else {
+        day_end
+    };
+    if to <= from {
+        return (0, 0);
+    }
+
+    match get_matured_time(stake_entry, staking_pool) {
+        Some(matured_time) => {
+            let locked_seconds = (matured_time - from).clamp(0, to - from);
+            let matured_seconds = if get_accrual_weights(staking_pool, day).matured_weight > 0 {
+                to - from - locked_seconds
+            } else {
+                0
+            };
+            (locked_seconds, matured_seconds)
+        }
+        None => (to - from, 0),
+    }
+}
+
+pub fn calculate_claimable_rewards(
+    stake_entry: &StakeEntry,
+    staking_pool: &StakingPool,
+    boost_bps: u16,
+    current_time: i64,
+    is_unstaking: bool,
+) -> Result<u64> {
+    let current_epoch = get_claim_epoch_number(staking_pool, current_time)?;
+    let last_claimed_epoch = stake_entry.last_claim_week;
+    
+    // Can only claim up to previous epoch (not current epoch) when unstaking,
+    // pro-rata accrual pays the unfinished epoch up to now instead
+    let claimable_up_to_epoch = match (is_unstaking, staking_pool.accrual_mode) {
+        (true, AccrualMode::ProRata) => current_epoch + 1,
+        (true, AccrualMode::WholeDays) => current_epoch.saturating_sub(1),
+        (false, _) => current_epoch,
+    };
+
+    let end_day = get_claim_epoch_start_day(staking_pool, claimable_up_to_epoch);
+
+    // Rewards settled before a boost change are claimable once their epoch has ended
+    let total_rewards = get_settled_rewards(stake_entry, end_day)?;
+
+    if claimable_up_to_epoch <= last_claimed_epoch {
+        return Ok(total_rewards);
+    }
+
+    // Calculate day range, days before accrued_through_day are already settled
+    let start_day = get_claim_epoch_start_day(staking_pool, last_claimed_epoch)
+        .max(stake_entry.accrued_through_day);
+
+    let rewards = calculate_rewards_for_days(
+        stake_entry,
+        staking_pool,
+        boost_bps,
+        start_day,
+        end_day,
+        current_time,
+        is_unstaking,
+    )?;
+
+    total_rewards
+        .checked_add(rewards)
+        .ok_or(StakingError::Overflow.into())
+}
+
+// Settled rewards claimable up to end_day, pending ones belong to the epoch of the last settled day
+fn get_settled_rewards(stake_entry: &StakeEntry, end_day: u64) -> Result<u64> {
+    if end_day < stake_entry.accrued_through_day {
+        return Ok(stake_entry.accrued_rewards);
+    }
+
+    stake_entry
+        .accrued_rewards
+        .checked_add(stake_entry.pending_rewards)
+        .ok_or(StakingError::Overflow.into())
+}
+
+// Moves rewards up to today into the stake entry with the boost in effect so far. Days of
+// completed claim epochs go to accrued_rewards, days of the current epoch to pending_rewards
+pub fn settle_rewards(
+    stake_entry: &mut StakeEntry,
+    staking_pool: &StakingPool,
+    boost_bps: u16,
+    current_time: i64,
+) -> Result<()> {
+    let current_epoch = get_claim_epoch_number(staking_pool, current_time)?;
+    let epoch_start_day =
+        get_claim_epoch_start_day(staking_pool, current_epoch).min(MAX_DAILY_RATES as u64);
+    let current_day = ((current_time - staking_pool.program_start_time).max(0) / SECONDS_PER_DAY)
+        .min(MAX_DAILY_RATES as i64) as u64;
+
+    // Pending rewards of an epoch that has ended are claimable now
+    stake_entry.accrued_rewards = get_settled_rewards(stake_entry, epoch_start_day)?;
+    if epoch_start_day >= stake_entry.accrued_through_day {
+        stake_entry.pending_rewards = 0;
+    }
+
+    let start_day = get_claim_epoch_start_day(staking_pool, stake_entry.last_claim_week)
+        .max(stake_entry.accrued_through_day);
+    if current_day <= start_day {
+        return Ok(());
+    }
+
+    let settle = |from_day: u64, to_day: u64| {
+        calculate_rewards_for_days(
+            stake_entry,
+            staking_pool,
+            boost_bps,
+            from_day,
+            to_day,
+            current_time,
+            false,
+        )
+    };
+    let completed_rewards = settle(start_day, epoch_start_day.max(start_day))?;
+    let pending_rewards = settle(epoch_start_day.max(start_day), current_day)?;
+
+    stake_entry.accrued_rewards = stake_entry
+        .accrued_rewards
+        .checked_add(completed_rewards)
+        .ok_or(StakingError::Overflow)?;
+    stake_entry.pending_rewards = stake_entry
+        .pending_rewards
+        .checked_add(pending_rewards)
+        .ok_or(StakingError::Overflow)?;
+    stake_entry.accrued_through_day = current_day;
+
+    Ok(())
+}
+
+pub fn is_position_tokenized(stake_entry: &StakeEntry) -> bool {
+    stake_entry.position_mint != Pubkey::default()
+}
+
+// Boost applied to a stake, positions held as NFTs are never boosted
+pub fn get_stake_boost_bps(stake_entry: &StakeEntry, user_staking_account: &UserStakingAccount) -> u16 {
+    if is_position_tokenized(stake_entry) {
+        0
+    } else {
+        user_staking_account.boost_bps
+    }
+}
+
+// Owner of a tokenized stake is whoever holds its position NFT
+pub fn validate_stake_authority(
+    stake_entry: &StakeEntry,
+    signer: &Pubkey,
+    position_token_account: Option<&InterfaceAccount<TokenAccount>>,
+) -> Result<()> {
+    if !is_position_tokenized(stake_entry) {
+        require!(stake_entry.owner == *signer, StakingError::Unauthorized);
+        return Ok(());
+    }
+
+    let position_token_account =
+        position_token_account.ok_or(StakingError::PositionTokenized)?;
+    require!(
+        position_token_account.mint == stake_entry.position_mint
+            && position_token_account.owner == *signer
+            && position_token_account.amount == 1,
+        StakingError::InvalidPositionToken
+    );
+
+    Ok(())
+}
+
+// Rewards and bonus a stake can claim now, unstaked stakes only keep what was withheld
+// while the wallet was denied
+pub fn get_stake_payout(
+    stake_entry: &StakeEntry,
+    staking_pool: &StakingPool,
+    boost_bps: u16,
+    current_time: i64,
+) -> Result<(u64, u64)> {
+    if !stake_entry.is_active {
+        return Ok((stake_entry.accrued_rewards, stake_entry.withheld_bonus));
+    }
+
+    let rewards = calculate_claimable_rewards(stake_entry, staking_pool, boost_bps, current_time, false)?;
+    let bonus = calculate_pending_bonus(stake_entry, staking_pool)?;
+
+    Ok((rewards, bonus))
+}
+
+// Pays out a stake's rewards and bonus up to now, returns (rewards, bonus)
+pub fn claim_stake_entry(
+    stake_entry: &mut StakeEntry,
+    staking_pool: &StakingPool,
+    boost_bps: u16,
+    current_time: i64,
+) -> Result<(u64, u64)> {
+    let (rewards, bonus) = get_stake_payout(stake_entry, staking_pool, boost_bps, current_time)?;
+
+    stake_entry.last_claim_time = current_time;
+    stake_entry.last_claim_week = get_claim_epoch_number(staking_pool, current_time)?;
+    // Pending rewards of the current epoch were not paid
+    let end_day = get_claim_epoch_start_day(staking_pool, stake_entry.last_claim_week);
+    if end_day >= stake_entry.accrued_through_day {
+        stake_entry.pending_rewards = 0;
+    }
+    stake_entry.accrued_rewards = 0;
+    stake_entry.withheld_bonus = 0;
+    stake_entry.bonus_debt = get_bonus_debt(staking_pool, stake_entry.weighted_amount)?;
+    stake_entry.total_claimed = stake_entry
+        .total_claimed
+        .checked_add(rewards)
+        .ok_or(StakingError::Overflow)?
+        .checked_add(bonus)
+        .ok_or(StakingError::Overflow)?;
+
+    Ok((rewards, bonus))
+}
+
+// Referrer share of rewards paid to a referred stake, on top of the staker's rewards.
+// It waits on the stake entry until the referrer claims it
+pub fn accrue_referral_reward(
+    stake_entry: &mut StakeEntry,
+    staking_pool: &StakingPool,
+    rewards: u64,
+) -> Result<u64> {
+    if stake_entry.referrer == Pubkey::default() {
+        return Ok(0);
+    }
+
+    let share = (rewards as u128)
+        .checked_mul(staking_pool.referral_bps as u128)
+        .ok_or(StakingError::Overflow)?
+        .checked_div(BASIS_POINTS as u128)
+        .ok_or(StakingError::DivisionByZero)? as u64;
+    stake_entry.referral_accrued = stake_entry
+        .referral_accrued
+        .checked_add(share)
+        .ok_or(StakingError::Overflow)?;
+
+    Ok(share)
+}
+
+// Referral rewards are released emission, reserved in the reward vault until claimed
+pub fn add_referral_pending(staking_pool: &mut StakingPool, amount: u64) -> Result<()> {
+    staking_pool.total_rewards_distributed = staking_pool
+        .total_rewards_distributed
+        .checked_add(amount)
+        .ok_or(StakingError::Overflow)?;
+    staking_pool.referral_pending = staking_pool
+        .referral_pending
+        .checked_add(amount)
+        .ok_or(StakingError::Overflow)?;
+
+    Ok(())
+}
+
+// Settles every active stake of the user, all of them must be passed in index order.
+// boost_bps is recomputed by the caller from the current boost_config
+pub fn settle_all_user_rewards<'info>(
+    remaining_accounts: &[AccountInfo<'info>],
+    user_staking_account: &UserStakingAccount,
+    staking_pool: &StakingPool,
+    staking_pool_key: &Pubkey,
+    program_id: &Pubkey,
+    current_time: i64,
+) -> Result<()> {
+    require!(
+        remaining_accounts.len() as u64 == user_staking_account.stake_count,
+        StakingError::MissingStakeEntries
+    );
+
+    for (index, stake_entry_account_info) in remaining_accounts.iter().enumerate() {
+        let index_bytes = (index as u64).to_le_bytes();
+
+        let (expected_pda, _bump) = Pubkey::find_program_address(
+            &[
+                b"stake_entry",
+                user_staking_account.owner.as_ref(),
+                staking_pool_key.as_ref(),
+                &index_bytes,
+            ],
+            program_id,
+        );
+
+        require!(
+            stake_entry_account_info.key() == expected_pda,
+            StakingError::InvalidStakeIndex
+        );
+
+        let mut stake_entry_data = stake_entry_account_info.try_borrow_mut_data()?;
+        let mut stake_entry = StakeEntry::try_deserialize(&mut stake_entry_data.as_ref())?;
+
+        // Position NFTs do not carry the owner's boost
+        if !stake_entry.is_active || is_position_tokenized(&stake_entry) {
+            continue;
+        }
+
+        settle_rewards(
+            &mut stake_entry,
+            staking_pool,
+            user_staking_account.boost_bps,
+            current_time,
+        )?;
+
+        let mut writer = stake_entry_data.as_mut();
+        stake_entry.try_serialize(&mut writer)?;
+    }
+
+    Ok(())
+}
+
+// Boost from the attached NFT and locked boost tokens, capped per user
+pub fn calculate_boost_bps(boost_config: &BoostConfig, boost_account: &BoostAccount) -> Result<u16> {
+    let mut boost_bps = 0u128;
+
+    if boost_account.nft_mint != Pubkey::default() {
+        boost_bps = boost_config.nft_boost_bps as u128;
+    }
+
+    if boost_config.token_boost_unit > 0 {
+        let token_boost = (boost_account.locked_amount as u128)
+            .checked_mul(boost_config.token_boost_bps as u128)
+            .ok_or(StakingError::Overflow)?
+            .checked_div(boost_config.token_boost_unit as u128)
+            .ok_or(StakingError::DivisionByZero)?;
+        boost_bps = boost_bps
+            .checked_add(token_boost)
+            .ok_or(StakingError::Overflow)?;
+    }
+
+    Ok(boost_bps.min(boost_config.max_boost_bps as u128) as u16)
+}
+
+// Eligible boost NFT: Token 2022 mint with supply 1, no decimals and membership in the configured group
+pub fn validate_boost_nft(
+    nft_mint: &InterfaceAccount<Mint>,
+    boost_config: &BoostConfig,
+) -> Result<()> {
+    require!(
+        boost_config.nft_collection != Pubkey::default(),
+        StakingError::BoostNotConfigured
+    );
+    require!(
+        nft_mint.decimals == 0 && nft_mint.supply == 1,
+        StakingError::InvalidBoostNft
+    );
+
+    let mint_info = nft_mint.to_account_info();
+    require!(
+        *mint_info.owner == anchor_spl::token_2022::ID,
+        StakingError::InvalidBoostNft
+    );
+
+    let mint_data = mint_info.try_borrow_data()?;
+    let mint_with_extensions = StateWithExtensions::<Token2022Mint>::unpack(&mint_data)
+        .map_err(|_| StakingError::InvalidBoostNft)?;
+    let member = mint_with_extensions
+        .get_extension::<TokenGroupMember>()
+        .map_err(|_| StakingError::InvalidBoostNft)?;
+
+    require!(
+        member.mint.to_bytes() == nft_mint.key().to_bytes()
+            && member.group.to_bytes() == boost_config.nft_collection.to_bytes(),
+        StakingError::InvalidBoostNft
+    );
+
+    Ok(())
+}
+
+pub fn validate_parameter_change(change: &ParameterChange) -> Result<()> {
+    match change {
+        ParameterChange::NormalizationK { new_k } => {
+            require!(*new_k > 0, StakingError::InvalidNormalizationK);
+        }
+        ParameterChange::TierWeights { weights } => {
+            require!(
+                weights.iter().all(|weight| *weight > 0),
+                StakingError::InvalidTierWeights
+            );
+        }
+        ParameterChange::FlexibleWeight { weight } => {
+            require!(*weight > 0, StakingError::InvalidTierWeights);
+        }
+        ParameterChange::MaturedWeight { .. } => {}
+        ParameterChange::AccrualMode { .. } => {}
+        ParameterChange::StakingCaps { caps } => {
+            // Every enabled cap must leave room for the minimum stake
+            let fits_min = |cap: u64| cap == 0 || cap >= caps.min_stake_amount;
+            require!(
+                fits_min(caps.max_stake_per_user)
+                    && fits_min(caps.pool_cap)
+                    && caps.max_stake_per_tier.iter().all(|cap| fits_min(*cap)),
+                StakingError::InvalidStakingCaps
+            );
+        }
+        ParameterChange::MaxPenalty { percent } => {
+            require!(*percent <= 100, StakingError::InvalidPenalty);
+        }
+        ParameterChange::Treasury { treasury } => {
+            require!(
+                *treasury != Pubkey::default(),
+                StakingError::InvalidTreasuryAccount
+            );
+        }
+        ParameterChange::EmissionSchedule { schedule } => {
+            // Cumulative amounts, so the schedule can never go backwards
+            require!(
+                schedule.windows(2).all(|pair| pair[0] <= pair[1]),
+                StakingError::InvalidEmissionSchedule
+            );
+        }
+        ParameterChange::DailyRate { day_index, .. } => {
+            require!(
+                *day_index < MAX_DAILY_RATES as u64,
+                StakingError::DayIndexOutOfBounds
+            );
+        }
+        ParameterChange::TimelockDelay { delay } => {
+            require!(
+                (0..=MAX_TIMELOCK_DELAY).contains(delay),
+                StakingError::InvalidTimelockDelay
+            );
+        }
+        ParameterChange::UnbondingCooldown { cooldown } => {
+            require!(
+                (0..=MAX_UNBONDING_COOLDOWN).contains(cooldown),
+                StakingError::InvalidUnbondingCooldown
+            );
+        }
+        ParameterChange::ReferralBps { bps } => {
+            require!(*bps <= MAX_REFERRAL_BPS, StakingError::InvalidReferralBps);
+        }
+        ParameterChange::PenaltyMode { .. } => {}
+        ParameterChange::GapFillMode { .. } => {}
+        ParameterChange::CrankTip { .. } => {}
+        ParameterChange::RewardFunder { .. } => {}
+        ParameterChange::RateSmoothing { config } => {
+            require!(
+                (config.mode == SmoothingMode::Off || config.ema_days > 0)
+                    && (config.max_rate == 0 || config.min_rate <= config.max_rate),
+                StakingError::InvalidRateSmoothing
+            );
+        }
+        ParameterChange::Governance { .. } => {}
+        ParameterChange::BoostConfig { config } => {
+            require!(
+                config.max_boost_bps as u64 <= MAX_BOOST_BPS,
+                StakingError::InvalidBoostConfig
+            );
+            require!(
+                config.token_boost_bps == 0 || config.token_boost_unit > 0,
+                StakingError::InvalidBoostConfig
+            );
+        }
+        ParameterChange::FeeRouting { fee_split, buyback } => {
+            let total_bps = fee_split.treasury_bps as u64
+                + fee_split.buyback_bps as u64
+                + fee_split.reward_vault_bps as u64;
+            require!(total_bps == BASIS_POINTS, StakingError::InvalidFeeSplit);
+            require!(
+                fee_split.buyback_bps == 0 || *buyback != Pubkey::default(),
+                StakingError::InvalidFeeDestination
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Returns (treasury, buyback, reward_vault) parts, rounding dust goes to treasury
+pub fn split_fee(amount: u64, fee_split: &FeeSplit) -> Result<(u64, u64, u64)> {
+    let share = |bps: u16| -> Result<u64> {
+        Ok((amount as u128)
+            .checked_mul(bps as u128)
+            .ok_or(StakingError::Overflow)?
+            .checked_div(BASIS_POINTS as u128)
+            .ok_or(StakingError::DivisionByZero)? as u64)
+    };
+
+    let buyback_amount = share(fee_split.buyback_bps)?;
+    let reward_vault_amount = share(fee_split.reward_vault_bps)?;
+    let treasury_amount = amount
+        .checked_sub(buyback_amount)
+        .ok_or(StakingError::Underflow)?
+        .checked_sub(reward_vault_amount)
+        .ok_or(StakingError::Underflow)?;
+
+    Ok((treasury_amount, buyback_amount, reward_vault_amount))
+}
+
+// Fee destinations must be token accounts of the stake mint under the same token program
+pub fn validate_fee_destination(
+    token_account: Option<&InterfaceAccount<TokenAccount>>,
+    expected: &Pubkey,
+    stake_mint: &InterfaceAccount<Mint>,
+) -> Result<()> {
+    let token_account = token_account.ok_or(StakingError::InvalidFeeDestination)?;
+
+    require!(
+        token_account.key() == *expected
+            && token_account.mint == stake_mint.key()
+            && token_account.to_account_info().owner == stake_mint.to_account_info().owner,
+        StakingError::InvalidFeeDestination
+    );
+
+    Ok(())
+}
+
+pub fn calculate_total_rewards_for_claim_all<'info>(
+    remaining_accounts: &[AccountInfo<'info>],
+    user: &Pubkey,
+    staking_pool: &StakingPool,
+    staking_pool_key: &Pubkey,
+    program_id: &Pubkey,
+    boost_bps: u16,
+    current_time: i64,
+) -> Result<(u64, u64, Vec<usize>)> {
+    let mut total_rewards = 0u64;
+    let mut total_bonus = 0u64;
+    let mut valid_stake_indices = Vec::new();
+    // msg!("Remaining accounts count: {}", remaining_accounts.len());
+
+    // Iterate through all passed StakeEntry accounts
+    for (index, stake_entry_account_info) in remaining_accounts.iter().enumerate() {
+        // msg!("Processing stake entry at index: {}", index);
+        // 1. Verify that PDA is correct
+        let index_bytes = (index as u64).to_le_bytes();
+
+        let (expected_pda, _bump) = Pubkey::find_program_address(
+            &[
+                b"stake_entry",
+                user.as_ref(),
+                staking_pool_key.as_ref(),
+                &index_bytes,
+            ],
+            program_id,
+        );
+
+        require!(
+            stake_entry_account_info.key() == expected_pda,
+            StakingError::InvalidStakeIndex
+        );
+
+        // 2. Deserialize StakeEntry
+        let stake_entry_data = stake_entry_account_info.try_borrow_data()?;
+        let stake_entry = StakeEntry::try_deserialize(&mut stake_entry_data.as_ref())?;
+
+        // 3. Skip stakes held as position NFTs, unstaked ones only pay what was withheld
+        if is_position_tokenized(&stake_entry) {
+            continue;
+        }
+
+        // 4. Verify owner
+        require!(stake_entry.owner == *user, StakingError::Unauthorized);
+
+        // 5. Calculate rewards for this stake
+        let (rewards, bonus) = get_stake_payout(
+            &stake_entry, 
+            staking_pool, 
+            boost_bps,
+            current_time, 
+        )?;
+        // msg!("Calculated rewards for stake {}: {}", index, rewards);
+
+        if rewards > 0 || bonus > 0 {
+            // 6. Accumulate total amount
+            total_rewards = total_rewards
+                .checked_add(rewards)
+                .ok_or(StakingError::Overflow)?;
+            total_bonus = total_bonus
+                .checked_add(bonus)
+                .ok_or(StakingError::Overflow)?;
+
+            // 7. Track indices of stakes with rewards
+            valid_stake_indices.push(index);
+        }
+    }
+
+    Ok((total_rewards, total_bonus, valid_stake_indices))
+}
+
+
+// Auto-renewing stakes always have a full lock ahead, so their voting power does not decay
+fn get_permanent_voting_power(stake_entry: &StakeEntry) -> Result<u64> {
+    let voting_power = (stake_entry.amount as u128)
+        .checked_mul(get_lock_period(stake_entry) as u128)
+        .ok_or(StakingError::Overflow)?
+        .checked_div(MAX_LOCK_SECONDS as u128)
+        .ok_or(StakingError::DivisionByZero)?;
+
+    Ok(voting_power as u64)
+}
+
+// Voting power of a stake: amount × remaining lock / max lock, decaying linearly to zero at the stake end
+// (at the horizon of the pool checkpoint at the latest). Flexible stakes have no lock and no voting power
+pub fn get_voting_power(
+    stake_entry: &StakeEntry,
+    staking_pool: &StakingPool,
+    at_time: i64,
+) -> Result<u64> {
+    if !stake_entry.is_active || at_time < stake_entry.start_time {
+        return Ok(0);
+    }
+    if stake_entry.auto_renew {
+        return get_permanent_voting_power(stake_entry);
+    }
+
+    let Some(end_time) = get_stake_end_time(stake_entry, staking_pool.month_convention) else {
+        return Ok(0);
+    };
+    let end_time = end_time.min(get_week_boundary(staking_pool, VE_WEEKS - 1));
+    if at_time >= end_time {
+        return Ok(0);
+    }
+
+    let voting_power = (stake_entry.amount as u128)
+        .checked_mul((end_time - at_time) as u128)
+        .ok_or(StakingError::Overflow)?
+        .checked_div(MAX_LOCK_SECONDS as u128)
+        .ok_or(StakingError::DivisionByZero)?;
+
+    Ok(voting_power as u64)
+}
+
+pub fn calculate_voting_power_for_user<'info>(
+    remaining_accounts: &[AccountInfo<'info>],
+    user: &Pubkey,
+    staking_pool: &StakingPool,
+    staking_pool_key: &Pubkey,
+    program_id: &Pubkey,
+    at_time: i64,
+) -> Result<u64> {
+    let mut total_voting_power = 0u64;
+
+    for (index, stake_entry_account_info) in remaining_accounts.iter().enumerate() {
+        let index_bytes = (index as u64).to_le_bytes();
+
+        let (expected_pda, _bump) = Pubkey::find_program_address(
+            &[
+                b"stake_entry",
+                user.as_ref(),
+                staking_pool_key.as_ref(),
+                &index_bytes,
+            ],
+            program_id,
+        );
+
+        require!(
+            stake_entry_account_info.key() == expected_pda,
+            StakingError::InvalidStakeIndex
+        );
+
+        let stake_entry_data = stake_entry_account_info.try_borrow_data()?;
+        let stake_entry = StakeEntry::try_deserialize(&mut stake_entry_data.as_ref())?;
+
+        if !stake_entry.is_active || is_position_tokenized(&stake_entry) {
+            continue;
+        }
+
+        require!(stake_entry.owner == *user, StakingError::Unauthorized);
+
+        total_voting_power = total_voting_power
+            .checked_add(get_voting_power(&stake_entry, staking_pool, at_time)?)
+            .ok_or(StakingError::Overflow)?;
+    }
+
+    Ok(total_voting_power)
+}
+
+// The pool total treats every stake as ending at the next week boundary, so it is an upper bound
+// of the sum of user voting powers. Stakes ending after the last tracked week count as ending there
+fn get_voting_end_week(staking_pool: &StakingPool, stake_entry: &StakeEntry) -> Option<usize> {
+    let end_time = get_stake_end_time(stake_entry, staking_pool.month_convention)?;
+    let seconds_to_end = (end_time - staking_pool.program_start_time).max(0);
+    let week = ((seconds_to_end + SECONDS_PER_WEEK - 1) / SECONDS_PER_WEEK) as usize;
+
+    Some(week.min(VE_WEEKS - 1))
+}
+
+fn get_week_boundary(staking_pool: &StakingPool, week: usize) -> i64 {
+    staking_pool.program_start_time + week as i64 * SECONDS_PER_WEEK
+}
+
+// Bias (voting power × MAX_LOCK_SECONDS) and slope of the pool at at_time, walking weekly slope changes
+fn advance_voting_power(staking_pool: &StakingPool, at_time: i64) -> Result<(u128, u64)> {
+    let mut bias = staking_pool.ve_bias;
+    let mut slope = staking_pool.ve_slope;
+    let mut time = staking_pool.ve_checkpoint_time;
+
+    if at_time <= time {
+        return Ok((bias, slope));
+    }
+
+    let mut week = ((time - staking_pool.program_start_time) / SECONDS_PER_WEEK + 1) as usize;
+    while week < VE_WEEKS {
+        let boundary = get_week_boundary(staking_pool, week);
+        if boundary > at_time {
+            break;
+        }
+
+        bias = bias.saturating_sub(slope as u128 * (boundary - time) as u128);
+        slope = slope.saturating_sub(staking_pool.ve_slope_changes[week]);
+        time = boundary;
+        week += 1;
+    }
+
+    bias = bias.saturating_sub(slope as u128 * (at_time - time) as u128);
+    Ok((bias, slope))
+}
+
+pub fn get_total_voting_power(staking_pool: &StakingPool, at_time: i64) -> Result<u64> {
+    require!(
+        at_time >= staking_pool.ve_checkpoint_time,
+        StakingError::InvalidVotingPowerTime
+    );
+
+    let (bias, _slope) = advance_voting_power(staking_pool, at_time)?;
+    ((bias / MAX_LOCK_SECONDS as u128) as u64)
+        .checked_add(staking_pool.ve_permanent)
+        .ok_or(StakingError::Overflow.into())
+}
+
+pub fn checkpoint_voting_power(staking_pool: &mut StakingPool, current_time: i64) -> Result<()> {
+    if current_time <= staking_pool.ve_checkpoint_time {
+        return Ok(());
+    }
+
+    let (bias, slope) = advance_voting_power(staking_pool, current_time)?;
+    // Stakes dropped from the slope reached their lock end
+    staking_pool.total_matured = staking_pool
+        .total_matured
+        .checked_add(staking_pool.ve_slope.saturating_sub(slope))
+        .ok_or(StakingError::Overflow)?;
+    staking_pool.ve_bias = bias;
+    staking_pool.ve_slope = slope;
+    staking_pool.ve_checkpoint_time = current_time;
+
+    Ok(())
+}
+
+pub fn add_stake_voting_power(
+    staking_pool: &mut StakingPool,
+    stake_entry: &StakeEntry,
+    current_time: i64,
+) -> Result<()> {
+    checkpoint_voting_power(staking_pool, current_time)?;
+
+    if stake_entry.auto_renew {
+        staking_pool.ve_permanent = staking_pool
+            .ve_permanent
+            .checked_add(get_permanent_voting_power(stake_entry)?)
+            .ok_or(StakingError::Overflow)?;
+        return Ok(());
+    }
+
+    let Some(week) = get_voting_end_week(staking_pool, stake_entry) else {
+        return Ok(());
+    };
+    let boundary = get_week_boundary(staking_pool, week);
+    if boundary <= current_time {
+        return Ok(());
+    }
+
+    let bias = (stake_entry.amount as u128)
+        .checked_mul((boundary - current_time) as u128)
+        .ok_or(StakingError::Overflow)?;
+    staking_pool.ve_bias = staking_pool
+        .ve_bias
+        .checked_add(bias)
+        .ok_or(StakingError::Overflow)?;
+    staking_pool.ve_slope = staking_pool
+        .ve_slope
+        .checked_add(stake_entry.amount)
+        .ok_or(StakingError::Overflow)?;
+    staking_pool.ve_slope_changes[week] = staking_pool.ve_slope_changes[week]
+        .checked_add(stake_entry.amount)
+        .ok_or(StakingError::Overflow)?;
+
+    Ok(())
+}
+
+pub fn remove_stake_voting_power(
+    staking_pool: &mut StakingPool,
+    stake_entry: &StakeEntry,
+    current_time: i64,
+) -> Result<()> {
+    checkpoint_voting_power(staking_pool, current_time)?;
+
+    if stake_entry.auto_renew {
+        staking_pool.ve_permanent = staking_pool
+            .ve_permanent
+            .saturating_sub(get_permanent_voting_power(stake_entry)?);
+        return Ok(());
+    }
+
+    let Some(week) = get_voting_end_week(staking_pool, stake_entry) else {
+        return Ok(());
+    };
+    let boundary = get_week_boundary(staking_pool, week);
+    if boundary <= current_time {
+        // Already decayed and dropped from the slope, the stake leaves the matured bucket
+        staking_pool.total_matured = staking_pool
+            .total_matured
+            .saturating_sub(stake_entry.amount);
+        return Ok(());
+    }
+
+    let bias = (stake_entry.amount as u128)
+        .checked_mul((boundary - current_time) as u128)
+        .ok_or(StakingError::Overflow)?;
+    staking_pool.ve_bias = staking_pool.ve_bias.saturating_sub(bias);
+    staking_pool.ve_slope = staking_pool.ve_slope.saturating_sub(stake_entry.amount);
+    staking_pool.ve_slope_changes[week] =
+        staking_pool.ve_slope_changes[week].saturating_sub(stake_entry.amount);
+
+    Ok(())
+}
+
+// Position NFT metadata shown by wallets and marketplaces
+pub fn get_position_metadata(
+    stake_entry: &StakeEntry,
+    month_convention: MonthConvention,
+) -> Vec<(String, String)> {
+    let tier = if is_flexible_stake(stake_entry) {
+        "flexible".to_string()
+    } else {
+        format!("{} months", stake_entry.duration_months)
+    };
+    let mut metadata = vec![
+        ("amount".to_string(), stake_entry.amount.to_string()),
+        ("tier".to_string(), tier),
+    ];
+    if let Some(end_time) = get_stake_end_time(stake_entry, month_convention) {
+        metadata.push(("unlock_date".to_string(), end_time.to_string()));
+    }
+
+    metadata
+}
+
+// Rewards of the liquid stake since the last compounding, for complete days only
+pub fn calculate_liquid_rewards(staking_pool: &StakingPool, current_time: i64) -> Result<u64> {
+    if staking_pool.liquid_staked == 0 {
+        return Ok(0);
+    }
+
+    let current_day = get_day_index(current_time, staking_pool.program_start_time)?;
+
+    let mut total_rewards = 0u64;
+
+    for day in staking_pool.liquid_compounded_day..current_day.min(MAX_DAILY_RATES as u64) {
+        let weights = get_accrual_weights(staking_pool, day);
+        let daily_reward = calculate_daily_reward(
+            staking_pool.liquid_staked,
+            get_accrual_rate(staking_pool, day),
+            get_tier_weight(&weights, LIQUID_DURATION_MONTHS)?,
+            0,
+            weights.normalization_k,
+        )?;
+
+        total_rewards = total_rewards
+            .checked_add(daily_reward)
+            .ok_or(StakingError::Overflow)?;
+    }
+
+    Ok(total_rewards.min(get_available_rewards(staking_pool, current_time)?))
+}
+
+pub fn get_liquid_exchange_rate(liquid_staked: u64, receipt_supply: u64) -> Result<u128> {
+    if receipt_supply == 0 {
+        return Ok(EXCHANGE_RATE_PRECISION);
+    }
+
+    (liquid_staked as u128)
+        .checked_mul(EXCHANGE_RATE_PRECISION)
+        .ok_or(StakingError::Overflow)?
+        .checked_div(receipt_supply as u128)
+        .ok_or(StakingError::DivisionByZero.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START_TIME: i64 = 1_704_067_200; // 2024-01-01 00:00:00 UTC
+    const RATE: u64 = 1_000;
+    const AMOUNT: u64 = 1_000_000_000_000_000; // 1M tokens
+
+    // Account data as allocated, before an instruction writes to it
+    fn zeroed<T: AnchorDeserialize + Space>() -> T {
+        T::deserialize(&mut vec![0u8; T::INIT_SPACE].as_slice()).unwrap()
+    }
+
+    // Only what the tests rely on is set, tests override what they need
+    fn test_pool() -> StakingPool {
+        let mut pool: StakingPool = zeroed();
+        pool.program_start_time = START_TIME;
+        pool.normalization_k = 250;
+        pool.tier_weights = DEFAULT_TIER_WEIGHTS;
+        pool.emission_schedule = DEFAULT_EMISSION_SCHEDULE;
+        pool.daily_rates = vec![RATE; MAX_DAILY_RATES];
+        pool
+    }
+
+    fn test_stake_entry(duration_months: u8, start_time: i64) -> StakeEntry {
+        let mut stake_entry: StakeEntry = zeroed();
+        stake_entry.amount = AMOUNT;
+        stake_entry.start_time = start_time;
+        stake_entry.duration_months = duration_months;
+        stake_entry.last_claim_time = start_time;
+        stake_entry.is_active = true;
+        stake_entry
+    }
+
+    #[test]
+    fn redistributed_penalty_is_shared_by_weight() {
+        let mut pool = test_pool();
+        pool.penalty_mode = PenaltyMode::Redistribute;
+        let [mut three_months, twelve_months, unstaked] = [3, 12, 3].map(|duration_months| {
+            let mut stake_entry = test_stake_entry(duration_months, START_TIME);
+            stake_entry.weighted_amount =
+                calculate_weighted_amount(&pool, AMOUNT, duration_months).unwrap();
+            stake_entry
+        });
+        pool.total_weighted_stake =
+            three_months.weighted_amount + twelve_months.weighted_amount + unstaked.weighted_amount;
+
+        // Early unstake: the penalty goes to the stakes that remain
+        let penalty = 4_000_000;
+        pool.total_weighted_stake -= unstaked.weighted_amount;
+        distribute_bonus(&mut pool, penalty).unwrap();
+
+        assert_eq!(pool.bonus_pot, penalty);
+        assert_eq!(calculate_pending_bonus(&three_months, &pool).unwrap(), 1_000_000);
+        assert_eq!(calculate_pending_bonus(&twelve_months, &pool).unwrap(), 3_000_000);
+
+        // Stakes created afterwards start from the current accumulator
+        let mut later = test_stake_entry(12, START_TIME);
+        later.weighted_amount = calculate_weighted_amount(&pool, AMOUNT, 12).unwrap();
+        later.bonus_debt = get_bonus_debt(&pool, later.weighted_amount).unwrap();
+        assert_eq!(calculate_pending_bonus(&later, &pool).unwrap(), 0);
+
+        // Claiming pays the bonus once and moves bonus_debt up to date
+        let (_, bonus) = claim_stake_entry(&mut three_months, &pool, 0, START_TIME).unwrap();
+        assert_eq!(bonus, 1_000_000);
+        assert_eq!(calculate_pending_bonus(&three_months, &pool).unwrap(), 0);
+        assert_eq!(calculate_pending_bonus(&twelve_months, &pool).unwrap(), 3_000_000);
+    }
+
+    #[test]
+    fn matured_days_earn_the_matured_weight() {
+        let mut pool = test_pool();
+        let stake_entry = test_stake_entry(3, START_TIME);
+        let current_time = START_TIME + 100 * SECONDS_PER_DAY;
+        // 3 months of 30 days, the lock ends on day 90 and the stake matures at the next week
+        // boundary (day 91), when it also leaves the earning stake
+        let locked_daily = calculate_daily_reward(AMOUNT, RATE, 10, 0, 250).unwrap();
+        let matured_daily = calculate_daily_reward(AMOUNT, RATE, 5, 0, 250).unwrap();
+
+        assert_eq!(
+            calculate_rewards_for_days(&stake_entry, &pool, 0, 88, 92, current_time, false).unwrap(),
+            3 * locked_daily
+        );
+
+        pool.matured_weight = 5;
+        assert_eq!(
+            calculate_rewards_for_days(&stake_entry, &pool, 0, 88, 92, current_time, false).unwrap(),
+            3 * locked_daily + matured_daily
+        );
+    }
+
+    #[test]
+    fn matured_stake_leaves_the_earning_stake() {
+        let mut pool = test_pool();
+        pool.total_staked = 2 * AMOUNT; // the locked stake and a flexible one
+        pool.total_funded = TOTAL_REWARD_POOL;
+        pool.ve_checkpoint_time = START_TIME;
+        let stake_entry = test_stake_entry(3, START_TIME);
+        add_stake_voting_power(&mut pool, &stake_entry, START_TIME).unwrap();
+
+        // The lock ends on day 90, the stake matures at the next week boundary (day 91)
+        checkpoint_voting_power(&mut pool, START_TIME + 90 * SECONDS_PER_DAY + 43_200).unwrap();
+        assert_eq!(pool.total_matured, 0);
+        assert_eq!(get_earning_stake(&pool), 2 * AMOUNT);
+
+        let current_time = START_TIME + 92 * SECONDS_PER_DAY;
+        pool.rate_snapshot.day_index = 91;
+        update_daily_rate(&mut pool, current_time).unwrap();
+        assert_eq!(pool.total_matured, AMOUNT);
+        assert_eq!(get_earning_stake(&pool), AMOUNT);
+
+        // The rate after the lock end is spread over the stake that still earns
+        let available_rewards = get_available_rewards(&pool, current_time).unwrap();
+        assert_eq!(
+            pool.daily_rates[92],
+            calculate_base_apy(AMOUNT, available_rewards).unwrap()
+        );
+
+        // With a matured weight the matured stake keeps earning
+        pool.matured_weight = 5;
+        assert_eq!(get_earning_stake(&pool), 2 * AMOUNT);
+
+        remove_stake_voting_power(&mut pool, &stake_entry, current_time).unwrap();
+        assert_eq!(pool.total_matured, 0);
+    }
+
+    fn utc(year: i64, month: u32, day: u32, seconds_of_day: i64) -> i64 {
+        days_from_civil(year, month, day) * SECONDS_PER_DAY + seconds_of_day
+    }
+
+    #[test]
+    fn civil_days_round_trip() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2024, 1, 1), START_TIME / SECONDS_PER_DAY);
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(days_from_civil(2024, 2, 29)), (2024, 2, 29));
+        assert_eq!(days_from_civil(2024, 3, 1) - days_from_civil(2024, 2, 28), 2);
+        assert_eq!(days_from_civil(2023, 3, 1) - days_from_civil(2023, 2, 28), 1);
+
+        for days in (0..30_000).step_by(7) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn add_months_clamps_to_the_month_end() {
+        let calendar = MonthConvention::Calendar;
+
+        assert_eq!(
+            add_months(utc(2024, 1, 31, 3_600), 1, calendar),
+            utc(2024, 2, 29, 3_600)
+        );
+        assert_eq!(
+            add_months(utc(2023, 1, 31, 3_600), 1, calendar),
+            utc(2023, 2, 28, 3_600)
+        );
+        assert_eq!(
+            add_months(utc(2024, 1, 31, 0), 3, calendar),
+            utc(2024, 4, 30, 0)
+        );
+    }
+
+    #[test]
+    fn add_months_from_a_leap_day() {
+        let calendar = MonthConvention::Calendar;
+
+        assert_eq!(add_months(utc(2024, 2, 29, 0), 12, calendar), utc(2025, 2, 28, 0));
+        assert_eq!(add_months(utc(2024, 2, 29, 0), 48, calendar), utc(2028, 2, 29, 0));
+        assert_eq!(add_months(utc(2024, 2, 29, 0), 1, calendar), utc(2024, 3, 29, 0));
+    }
+
+    #[test]
+    fn add_months_rolls_over_the_year() {
+        let calendar = MonthConvention::Calendar;
+
+        assert_eq!(add_months(utc(2024, 11, 15, 0), 3, calendar), utc(2025, 2, 15, 0));
+        assert_eq!(add_months(utc(2024, 12, 31, 0), 1, calendar), utc(2025, 1, 31, 0));
+        assert_eq!(add_months(utc(2024, 12, 31, 0), 14, calendar), utc(2026, 2, 28, 0));
+        assert_eq!(
+            add_months(utc(2024, 12, 31, 0), 1, MonthConvention::ThirtyDays),
+            utc(2025, 1, 30, 0)
+        );
+    }
+
+    #[test]
+    fn months_elapsed_at_the_anniversary() {
+        let calendar = MonthConvention::Calendar;
+        let start = utc(2024, 1, 31, 36_000);
+        let anniversary = utc(2024, 2, 29, 36_000);
+
+        assert_eq!(get_months_elapsed(start, anniversary - 1, calendar).unwrap(), 0);
+        assert_eq!(get_months_elapsed(start, anniversary, calendar).unwrap(), 1);
+        assert_eq!(get_months_elapsed(start, anniversary + 1, calendar).unwrap(), 1);
+
+        // Across the year end
+        let start = utc(2024, 11, 15, 43_200);
+        let anniversary = utc(2025, 2, 15, 43_200);
+        assert_eq!(get_months_elapsed(start, anniversary - 1, calendar).unwrap(), 2);
+        assert_eq!(get_months_elapsed(start, anniversary, calendar).unwrap(), 3);
+        assert_eq!(get_months_elapsed(start, anniversary + 1, calendar).unwrap(), 3);
+    }
+
+    #[test]
+    fn months_elapsed_thirty_days() {
+        let thirty_days = MonthConvention::ThirtyDays;
+        let anniversary = START_TIME + 30 * SECONDS_PER_DAY;
+
+        assert_eq!(get_months_elapsed(START_TIME, anniversary - 1, thirty_days).unwrap(), 0);
+        assert_eq!(get_months_elapsed(START_TIME, anniversary, thirty_days).unwrap(), 1);
+        assert_eq!(get_months_elapsed(START_TIME, anniversary + 1, thirty_days).unwrap(), 1);
+    }
+
+    #[test]
+    fn pro_rata_stake_mid_day() {
+        let pool = test_pool();
+        let stake_entry = test_stake_entry(3, START_TIME + 2 * SECONDS_PER_DAY + 21_600);
+        let current_time = START_TIME + 10 * SECONDS_PER_DAY;
+
+        assert_eq!(get_pro_rata_seconds(1, &stake_entry, &pool, false, current_time), (0, 0));
+        assert_eq!(
+            get_pro_rata_seconds(2, &stake_entry, &pool, false, current_time),
+            (SECONDS_PER_DAY - 21_600, 0)
+        );
+        assert_eq!(
+            get_pro_rata_seconds(3, &stake_entry, &pool, false, current_time),
+            (SECONDS_PER_DAY, 0)
+        );
+    }
+
+    #[test]
+    fn pro_rata_unstake_mid_day() {
+        let pool = test_pool();
+        let stake_entry = test_stake_entry(FLEXIBLE_DURATION_MONTHS, START_TIME + 2 * SECONDS_PER_DAY + 21_600);
+
+        // Unstaked on the day of the stake
+        let current_time = START_TIME + 2 * SECONDS_PER_DAY + 50_400;
+        assert_eq!(get_pro_rata_seconds(2, &stake_entry, &pool, true, current_time), (28_800, 0));
+
+        // Unstaked on a later day, that day counts up to now
+        let current_time = START_TIME + 5 * SECONDS_PER_DAY + 3_600;
+        assert_eq!(get_pro_rata_seconds(5, &stake_entry, &pool, true, current_time), (3_600, 0));
+        assert_eq!(
+            get_pro_rata_seconds(4, &stake_entry, &pool, true, current_time),
+            (SECONDS_PER_DAY, 0)
+        );
+    }
+
+    #[test]
+    fn pro_rata_stake_matures_at_the_week_boundary() {
+        let mut pool = test_pool();
+        let stake_entry = test_stake_entry(3, START_TIME + 43_200);
+        let current_time = START_TIME + 100 * SECONDS_PER_DAY;
+        // 3 months of 30 days, the lock ends at noon of day 90 and the stake matures on day 91
+        let lock_end_day = 90;
+
+        assert_eq!(
+            get_pro_rata_seconds(lock_end_day, &stake_entry, &pool, false, current_time),
+            (SECONDS_PER_DAY, 0)
+        );
+        assert_eq!(
+            get_pro_rata_seconds(lock_end_day + 1, &stake_entry, &pool, false, current_time),
+            (0, 0)
+        );
+
+        pool.matured_weight = 5;
+        assert_eq!(
+            get_pro_rata_seconds(lock_end_day, &stake_entry, &pool, false, current_time),
+            (SECONDS_PER_DAY, 0)
+        );
+        assert_eq!(
+            get_pro_rata_seconds(lock_end_day + 1, &stake_entry, &pool, false, current_time),
+            (0, SECONDS_PER_DAY)
+        );
+    }
+
+    #[test]
+    fn smoothing_ema() {
+        let mut pool = test_pool();
+        pool.rate_smoothing = RateSmoothing {
+            mode: SmoothingMode::Ema,
+            ema_days: 3,
+            ..RateSmoothing::default()
+        };
+
+        // (2_000 * 2 + 1_000 * 2) / 4 against the previous day's 1_000
+        assert_eq!(get_smoothed_daily_rate(&pool, 5, 2_000), 1_500);
+        assert_eq!(get_smoothed_daily_rate(&pool, 5, 0), 500);
+    }
+
+    #[test]
+    fn smoothing_max_daily_change() {
+        let mut pool = test_pool();
+        pool.rate_smoothing.max_daily_change_bps = 1_000;
+
+        assert_eq!(get_smoothed_daily_rate(&pool, 5, 2_000), 1_100);
+        assert_eq!(get_smoothed_daily_rate(&pool, 5, 500), 900);
+        assert_eq!(get_smoothed_daily_rate(&pool, 5, 1_050), 1_050);
+
+        // EMA gives 1_500 first, then the change is limited to +20%
+        pool.rate_smoothing = RateSmoothing {
+            mode: SmoothingMode::Ema,
+            ema_days: 3,
+            max_daily_change_bps: 2_000,
+            ..RateSmoothing::default()
+        };
+        assert_eq!(get_smoothed_daily_rate(&pool, 5, 2_000), 1_200);
+    }
+
+    #[test]
+    fn smoothing_bounds() {
+        let mut pool = test_pool();
+        pool.rate_smoothing.min_rate = 800;
+        pool.rate_smoothing.max_rate = 1_200;
+
+        assert_eq!(get_smoothed_daily_rate(&pool, 5, 100), 800);
+        assert_eq!(get_smoothed_daily_rate(&pool, 5, 5_000), 1_200);
+        assert_eq!(get_smoothed_daily_rate(&pool, 5, 1_000), 1_000);
+    }
+
+    #[test]
+    fn smoothing_without_a_previous_rate_applies_only_the_bounds() {
+        let mut pool = test_pool();
+        pool.rate_smoothing = RateSmoothing {
+            mode: SmoothingMode::Ema,
+            ema_days: 3,
+            max_daily_change_bps: 1_000,
+            min_rate: 0,
+            max_rate: 3_000,
+        };
+        pool.daily_rates[4] = 0;
+
+        assert_eq!(get_smoothed_daily_rate(&pool, 5, 2_000), 2_000);
+        assert_eq!(get_smoothed_daily_rate(&pool, 5, 5_000), 3_000);
+        // Day 0 has no previous day
+        assert_eq!(get_smoothed_daily_rate(&pool, 0, 2_000), 2_000);
+    }
+
+    #[test]
+    fn boost_pays_more() {
+        let pool = test_pool();
+        let stake_entry = test_stake_entry(3, START_TIME);
+        let current_time = START_TIME + 14 * SECONDS_PER_DAY;
+
+        let unboosted =
+            calculate_rewards_for_days(&stake_entry, &pool, 0, 0, 14, current_time, false).unwrap();
+        let boosted =
+            calculate_rewards_for_days(&stake_entry, &pool, 5_000, 0, 14, current_time, false).unwrap();
+
+        let daily = calculate_daily_reward(AMOUNT, RATE, 10, 0, 250).unwrap();
+        let boosted_daily = calculate_daily_reward(AMOUNT, RATE, 10, 5_000, 250).unwrap();
+        assert_eq!(unboosted, 14 * daily);
+        assert_eq!(boosted, 14 * boosted_daily);
+        assert!(boosted_daily > daily);
+    }
+
+    #[test]
+    fn settled_days_of_the_current_epoch_wait_for_its_end() {
+        let mut pool = test_pool();
+        pool.claim_epoch = ClaimEpoch::Weekly;
+        let mut stake_entry = test_stake_entry(3, START_TIME);
+        let current_time = START_TIME + 10 * SECONDS_PER_DAY; // day 3 of the second week
+
+        settle_rewards(&mut stake_entry, &pool, 0, current_time).unwrap();
+
+        let daily = calculate_daily_reward(AMOUNT, RATE, 10, 0, 250).unwrap();
+        assert_eq!(stake_entry.accrued_through_day, 10);
+        assert_eq!(stake_entry.accrued_rewards, 7 * daily);
+        assert_eq!(stake_entry.pending_rewards, 3 * daily);
+
+        // Nothing of the current epoch becomes claimable early, a claim keeps it pending
+        let claim_time = START_TIME + 12 * SECONDS_PER_DAY;
+        assert_eq!(
+            claim_stake_entry(&mut stake_entry, &pool, 0, claim_time).unwrap(),
+            (7 * daily, 0)
+        );
+        assert_eq!(stake_entry.pending_rewards, 3 * daily);
+
+        let claim_time = START_TIME + 14 * SECONDS_PER_DAY;
+        assert_eq!(
+            claim_stake_entry(&mut stake_entry, &pool, 0, claim_time).unwrap(),
+            (7 * daily, 0)
+        );
+        assert_eq!(stake_entry.pending_rewards, 0);
+    }
+
+    #[test]
+    fn boost_change_applies_from_the_settled_day() {
+        let mut pool = test_pool();
+        pool.claim_epoch = ClaimEpoch::Weekly;
+        let mut stake_entry = test_stake_entry(3, START_TIME);
+        let claim_time = START_TIME + 21 * SECONDS_PER_DAY;
+
+        // Boost attached on day 10 and detached on day 17, settled with the boost before each change
+        settle_rewards(&mut stake_entry, &pool, 0, START_TIME + 10 * SECONDS_PER_DAY).unwrap();
+        settle_rewards(&mut stake_entry, &pool, 5_000, START_TIME + 17 * SECONDS_PER_DAY).unwrap();
+        assert_eq!(stake_entry.accrued_through_day, 17);
+
+        let daily = calculate_daily_reward(AMOUNT, RATE, 10, 0, 250).unwrap();
+        let boosted_daily = calculate_daily_reward(AMOUNT, RATE, 10, 5_000, 250).unwrap();
+        assert_eq!(
+            calculate_claimable_rewards(&stake_entry, &pool, 0, claim_time, false).unwrap(),
+            14 * daily + 7 * boosted_daily
+        );
+    }
+
+    #[test]
+    fn backfill_takes_several_calls() {
+        let mut pool = test_pool();
+        pool.total_staked = AMOUNT;
+        pool.total_funded = TOTAL_REWARD_POOL;
+        pool.raw_daily_rates = vec![0; MAX_DAILY_RATES];
+        let current_time = START_TIME + 40 * SECONDS_PER_DAY;
+
+        update_daily_rate(&mut pool, current_time).unwrap();
+        assert_eq!(pool.rate_snapshot.day_index, MAX_BACKFILL_DAYS);
+
+        update_daily_rate(&mut pool, current_time).unwrap();
+        assert_eq!(pool.rate_snapshot.day_index, 40);
+        assert!(pool.daily_rates[1..40].iter().all(|&rate| rate == RATE));
+        assert!(pool.raw_daily_rates[1..40].iter().all(|&rate| rate == 0));
+        assert!(pool.raw_daily_rates[40] > 0);
+        assert_eq!(pool.daily_rates[40], pool.raw_daily_rates[40]);
+    }
+
+    #[test]
+    fn backfill_zero_mode_fills_nothing() {
+        let mut pool = test_pool();
+        pool.total_staked = AMOUNT;
+        pool.total_funded = TOTAL_REWARD_POOL;
+        pool.raw_daily_rates = vec![0; MAX_DAILY_RATES];
+        pool.gap_fill_mode = GapFillMode::Zero;
+        let current_time = START_TIME + 10 * SECONDS_PER_DAY;
+
+        assert_eq!(get_accrual_rate(&pool, 5), 0);
+        update_daily_rate(&mut pool, current_time).unwrap();
+
+        assert_eq!(pool.rate_snapshot.day_index, 10);
+        assert!(pool.daily_rates[1..10].iter().all(|&rate| rate == 0));
+        assert!(pool.raw_daily_rates[1..10].iter().all(|&rate| rate == 0));
+        assert!(pool.daily_rates[10] > 0);
+    }
+
+    #[test]
+    fn backfill_carry_forward_matches_the_accrual_before_it() {
+        let mut pool = test_pool();
+        pool.total_staked = AMOUNT;
+        pool.total_funded = TOTAL_REWARD_POOL;
+        pool.raw_daily_rates = vec![0; MAX_DAILY_RATES];
+        pool.daily_rates[5] = 0; // not computed yet, accrues at the gap fill rate
+        let current_time = START_TIME + 10 * SECONDS_PER_DAY;
+
+        assert_eq!(get_accrual_rate(&pool, 5), RATE);
+        update_daily_rate(&mut pool, current_time).unwrap();
+
+        assert_eq!(get_computed_daily_rate(&pool, 5), RATE);
+        assert!(pool.daily_rates[1..10].iter().all(|&rate| rate == RATE));
+    }
+
+    #[test]
+    fn crank_tip_is_capped_by_the_budget() {
+        let mut pool = test_pool();
+        pool.rate_snapshot.day_index = 5;
+        pool.crank_tip = 100;
+
+        pool.crank_budget = 40;
+        assert_eq!(get_crank_tip(&pool, 4), 40);
+
+        pool.crank_budget = 500;
+        assert_eq!(get_crank_tip(&pool, 4), 100);
+
+        // Nothing finalized, nothing paid
+        assert_eq!(get_crank_tip(&pool, 5), 0);
+    }
+
+    #[test]
+    fn weight_change_applies_from_the_day_after_execution() {
+        let mut pool = test_pool();
+        let stake_entry = test_stake_entry(3, START_TIME);
+        let current_time = START_TIME + 20 * SECONDS_PER_DAY;
+
+        // Two changes executed during day 10, days up to 10 keep the weights before both
+        record_weight_change(&mut pool, START_TIME + 10 * SECONDS_PER_DAY + 3_600).unwrap();
+        pool.tier_weights = [20, 30, 40, 60];
+        record_weight_change(&mut pool, START_TIME + 10 * SECONDS_PER_DAY + 7_200).unwrap();
+        pool.normalization_k = 500;
+        assert_eq!(pool.weight_history.len(), 1);
+
+        let old_daily = calculate_daily_reward(AMOUNT, RATE, 10, 0, 250).unwrap();
+        let new_daily = calculate_daily_reward(AMOUNT, RATE, 20, 0, 500).unwrap();
+        assert_eq!(
+            calculate_rewards_for_days(&stake_entry, &pool, 0, 0, 14, current_time, false).unwrap(),
+            11 * old_daily + 3 * new_daily
+        );
+    }
+
+    #[test]
+    fn excess_rewards_keep_owed_buckets_after_program_end() {
+        let mut pool = test_pool();
+        pool.total_funded = TOTAL_REWARD_POOL;
+        pool.total_rewards_distributed = TOTAL_REWARD_POOL - 5_000;
+        pool.bonus_pot = 1_000;
+        pool.referral_pending = 2_000;
+        pool.crank_budget = 3_000;
+        let current_time = START_TIME + 400 * SECONDS_PER_DAY;
+
+        let vault_balance = 20_000;
+        assert_eq!(
+            get_excess_rewards(&pool, vault_balance, current_time).unwrap(),
+            vault_balance - 5_000 - 1_000 - 2_000 - 3_000
+        );
+    }
+
+    #[test]
+    fn voting_power_past_the_horizon_ends_at_the_last_tracked_week() {
+        let mut pool = test_pool();
+        let start_time = START_TIME + 120 * SECONDS_PER_WEEK;
+        pool.ve_checkpoint_time = start_time;
+        let stake_entry = test_stake_entry(12, start_time);
+        let horizon = START_TIME + (VE_WEEKS as i64 - 1) * SECONDS_PER_WEEK;
+
+        add_stake_voting_power(&mut pool, &stake_entry, start_time).unwrap();
+
+        let voting_power = get_voting_power(&stake_entry, &pool, start_time).unwrap();
+        assert_eq!(
+            voting_power as u128,
+            AMOUNT as u128 * (horizon - start_time) as u128 / MAX_LOCK_SECONDS as u128
+        );
+        assert_eq!(get_total_voting_power(&pool, start_time).unwrap(), voting_power);
+        assert_eq!(get_voting_power(&stake_entry, &pool, horizon).unwrap(), 0);
+        assert_eq!(get_total_voting_power(&pool, horizon).unwrap(), 0);
+    }
 }
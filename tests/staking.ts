@@ -12,6 +12,7 @@ import {
 } from "@solana/spl-token";
 import { assert, expect } from "chai";
 import BN from "bn.js";
+import { createHash } from "crypto";
 
 describe("staking_program", () => {
   // Configure the client to use the local cluster
@@ -33,6 +34,7 @@ describe("staking_program", () => {
   let user2StakeAccount: anchor.web3.PublicKey;
   let user2RewardAccount: anchor.web3.PublicKey;
   let treasuryRewardAccount: anchor.web3.PublicKey;
+  let authorityTokenAccount: anchor.web3.PublicKey;
 
   // PDAs
   let stakingPoolPDA: anchor.web3.PublicKey;
@@ -234,7 +236,7 @@ describe("staking_program", () => {
   describe("initialize", () => {
     it("should initialize the staking pool and vault accounts", async () => {
       await program.methods
-        .initialize(PROGRAM_END_DATE)
+        .initialize(PROGRAM_END_DATE, { thirtyDays: {} }, { weekly: {} }, new BN(86400))
         .accounts({
           authority: authority.publicKey,
           stakingPool: stakingPoolPDA,
@@ -260,6 +262,9 @@ describe("staking_program", () => {
       assert.equal(stakingPool.totalStaked.toNumber(), 0);
       assert.equal(stakingPool.totalRewardsDistributed.toNumber(), 0);
       assert.ok(stakingPool.programEndDate.eq(PROGRAM_END_DATE));
+      assert.deepEqual(stakingPool.monthConvention, { thirtyDays: {} });
+      assert.deepEqual(stakingPool.claimEpoch, { weekly: {} });
+      assert.equal(stakingPool.claimEpochOffset.toNumber(), 86400);
       assert.equal(stakingPool.dailyRates.length, 370);
       // lastRateUpdateDay field was removed from the StakingPool struct
 
@@ -286,17 +291,37 @@ describe("staking_program", () => {
       assert.equal(Number(rewardVaultAccount.amount), 0);
 
       // Fund the reward vault for testing
+      authorityTokenAccount = await createAssociatedTokenAccount(
+        provider.connection,
+        authority,
+        stakeMint,
+        authority.publicKey,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
       await mintTo(
         provider.connection,
         authority,
         stakeMint,
-        rewardVaultPDA,
+        authorityTokenAccount,
         authority,
         BigInt(REWARD_POOL_AMOUNT.toString()),
         undefined,
         undefined,
         TOKEN_2022_PROGRAM_ID
       );
+      await program.methods
+        .fundRewardVault(REWARD_POOL_AMOUNT)
+        .accounts({
+          funder: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          funderTokenAccount: authorityTokenAccount,
+          rewardVault: rewardVaultPDA,
+          stakeMint: stakeMint,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([authority])
+        .rpc();
 
       // Verify reward vault is funded
       const fundedRewardVault = await getAccount(
@@ -309,6 +334,112 @@ describe("staking_program", () => {
         fundedRewardVault.amount.toString(),
         REWARD_POOL_AMOUNT.toString()
       );
+
+      const fundedPool = await program.account.stakingPool.fetch(
+        stakingPoolPDA
+      );
+      assert.equal(
+        fundedPool.totalFunded.toString(),
+        REWARD_POOL_AMOUNT.toString()
+      );
+    });
+  });
+
+  describe("fund_reward_vault", () => {
+    it("should allow anyone to fund when no funder is whitelisted", async () => {
+      const fundAmount = new BN("1000000000"); // 1 token
+      await mintTo(
+        provider.connection,
+        authority,
+        stakeMint,
+        user2StakeAccount,
+        authority,
+        BigInt(fundAmount.toString()),
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const poolBefore = await program.account.stakingPool.fetch(stakingPoolPDA);
+
+      await program.methods
+        .fundRewardVault(fundAmount)
+        .accounts({
+          funder: user2.publicKey,
+          stakingPool: stakingPoolPDA,
+          funderTokenAccount: user2StakeAccount,
+          rewardVault: rewardVaultPDA,
+          stakeMint: stakeMint,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([user2])
+        .rpc();
+
+      const poolAfter = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(
+        poolAfter.totalFunded.sub(poolBefore.totalFunded).toString(),
+        fundAmount.toString()
+      );
+    });
+
+    it("should restrict the reward funder only through the timelock", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const changeId = pool.changeCount;
+      const [pendingChangePDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          changeId.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .queueParameterChange({ rewardFunder: { funder: authority.publicKey } })
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      // Queued only, funding stays open until the change is executed
+      const poolAfter = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.isTrue(poolAfter.rewardFunder.equals(anchor.web3.PublicKey.default));
+      const pendingChange = await program.account.pendingChange.fetch(pendingChangePDA);
+      assert.isTrue(pendingChange.change.rewardFunder.funder.equals(authority.publicKey));
+
+      await program.methods
+        .cancelParameterChange(changeId)
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+        })
+        .signers([authority])
+        .rpc();
+    });
+
+    it("should fail when funding zero tokens", async () => {
+      try {
+        await program.methods
+          .fundRewardVault(new BN(0))
+          .accounts({
+            funder: authority.publicKey,
+            stakingPool: stakingPoolPDA,
+            funderTokenAccount: authorityTokenAccount,
+            rewardVault: rewardVaultPDA,
+            stakeMint: stakeMint,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have thrown an error");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidAmount");
+      }
     });
   });
 
@@ -328,7 +459,7 @@ describe("staking_program", () => {
       );
 
       await program.methods
-        .stake(STAKE_AMOUNT, 3) // Auto-calculated index
+        .stake(STAKE_AMOUNT, 3, [], null) // Auto-calculated index
         .accounts({
           user: user1.publicKey,
           stakingPool: stakingPoolPDA,
@@ -398,7 +529,7 @@ describe("staking_program", () => {
     it("should fail with invalid duration", async () => {
       try {
         await program.methods
-          .stake(STAKE_AMOUNT, 5) // Invalid duration
+          .stake(STAKE_AMOUNT, 5, [], null) // Invalid duration
           .accounts({
             user: user2.publicKey,
             stakingPool: stakingPoolPDA,
@@ -427,7 +558,7 @@ describe("staking_program", () => {
       );
 
       await program.methods
-        .stake(STAKE_AMOUNT.mul(new BN(2)), 12) // User2's first stake
+        .stake(STAKE_AMOUNT.mul(new BN(2)), 12, [], null) // User2's first stake
         .accounts({
           user: user2.publicKey,
           stakingPool: stakingPoolPDA,
@@ -475,7 +606,7 @@ describe("staking_program", () => {
     it("should allow multiple stakes for the same user", async () => {
       // User1 creates a second stake
       await program.methods
-        .stake(STAKE_AMOUNT.div(new BN(2)), 6) // Second stake, auto-calculated index
+        .stake(STAKE_AMOUNT.div(new BN(2)), 6, [], null) // Second stake, auto-calculated index
         .accounts({
           user: user1.publicKey,
           stakingPool: stakingPoolPDA,
@@ -602,7 +733,7 @@ describe("staking_program", () => {
 
       // Now stake - the program should handle the existing ATA correctly
       await program.methods
-        .stake(STAKE_AMOUNT, 3)
+        .stake(STAKE_AMOUNT, 3, [], null)
         .accounts({
           user: testUser.publicKey,
           stakingPool: stakingPoolPDA,
@@ -918,7 +1049,7 @@ describe("staking_program", () => {
       );
 
       await program.methods
-        .stake(stakeAmount, 3)
+        .stake(stakeAmount, 3, [], null)
         .accounts({
           user: testUser.publicKey,
           userTokenAccount: testUserTokenAccount,
@@ -1081,7 +1212,7 @@ describe("staking_program", () => {
         );
 
       await program.methods
-        .stake(STAKE_AMOUNT.div(new BN(4)), 9) // Third stake with auto-calculated index
+        .stake(STAKE_AMOUNT.div(new BN(4)), 9, [], null) // Third stake with auto-calculated index
         .accounts({
           user: user1.publicKey,
           stakingPool: stakingPoolPDA,
@@ -1118,7 +1249,7 @@ describe("staking_program", () => {
       // Try to create another stake using the same PDA as the first stake (index 0)
       try {
         await program.methods
-          .stake(STAKE_AMOUNT, 3) // This should fail
+          .stake(STAKE_AMOUNT, 3, [], null) // This should fail
           .accounts({
             user: user1.publicKey,
             stakingPool: stakingPoolPDA,
@@ -1475,7 +1606,7 @@ describe("staking_program", () => {
 
       // Stake 1: 3 months
       await program.methods
-        .stake(stake1Amount, 3)
+        .stake(stake1Amount, 3, [], null)
         .accounts({
           user: testUser.publicKey,
           stakingPool: stakingPoolPDA,
@@ -1499,7 +1630,7 @@ describe("staking_program", () => {
 
       // Stake 2: 6 months
       await program.methods
-        .stake(stake2Amount, 6)
+        .stake(stake2Amount, 6, [], null)
         .accounts({
           user: testUser.publicKey,
           stakingPool: stakingPoolPDA,
@@ -1523,7 +1654,7 @@ describe("staking_program", () => {
 
       // Stake 3: 12 months
       await program.methods
-        .stake(stake3Amount, 12)
+        .stake(stake3Amount, 12, [], null)
         .accounts({
           user: testUser.publicKey,
           stakingPool: stakingPoolPDA,
@@ -1564,7 +1695,7 @@ describe("staking_program", () => {
       const stake4Amount = new BN(1_000_000_000_000); // 1M tokens
 
       await program.methods
-        .stake(stake4Amount, 3)
+        .stake(stake4Amount, 3, [], null)
         .accounts({
           user: testUser.publicKey,
           stakingPool: stakingPoolPDA,
@@ -1806,7 +1937,7 @@ describe("staking_program", () => {
         viewTestStakeEntries.push(stakeEntryPDA);
 
         await program.methods
-          .stake(stakeAmounts[i], durations[i])
+          .stake(stakeAmounts[i], durations[i], [], null)
           .accounts({
             user: viewTestUser.publicKey,
             stakingPool: stakingPoolPDA,
@@ -1926,7 +2057,7 @@ describe("staking_program", () => {
 
       // Создаем стейк
       await program.methods
-        .stake(new BN(1_000_000_000_000), 3)
+        .stake(new BN(1_000_000_000_000), 3, [], null)
         .accounts({
           user: viewTestUser.publicKey,
           stakingPool: stakingPoolPDA,
@@ -2168,7 +2299,7 @@ describe("staking_program", () => {
       
       // Stake for 3 months
       await program.methods
-        .stake(stakeAmount, 3)
+        .stake(stakeAmount, 3, [], null)
         .accounts({
           user: penaltyTestUser.publicKey,
           stakingPool: stakingPoolPDA,
@@ -2268,7 +2399,7 @@ describe("staking_program", () => {
       
       // Stake for 6 months (180 days)
       await program.methods
-        .stake(stakeAmount, 6)
+        .stake(stakeAmount, 6, [], null)
         .accounts({
           user: penaltyTestUser.publicKey,
           stakingPool: stakingPoolPDA,
@@ -2323,70 +2454,301 @@ describe("staking_program", () => {
     });
   });
 
-  describe("update_normalization_k", () => {
-    it("should update normalization_k when called by authority", async () => {
-      // Get initial normalization_k value
+  describe("parameter change queue", () => {
+    const getPendingChangePDA = (changeId: BN) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          changeId.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      )[0];
+
+    const queueChange = async (change: any, signer = authority) => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const changeId = pool.changeCount;
+      await program.methods
+        .queueParameterChange(change)
+        .accounts({
+          authority: signer.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: getPendingChangePDA(changeId),
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([signer])
+        .rpc();
+      return changeId;
+    };
+
+    it("should queue a normalization_k change without applying it", async () => {
       const poolBefore = await program.account.stakingPool.fetch(stakingPoolPDA);
-      const oldK = poolBefore.normalizationK;
-      assert.equal(oldK.toString(), "250", "Initial normalization_k should be 250");
+      assert.equal(poolBefore.normalizationK.toString(), "250", "Initial normalization_k should be 250");
+
+      const changeId = await queueChange({ normalizationK: { newK: new BN(500) } });
+
+      const pendingChange = await program.account.pendingChange.fetch(
+        getPendingChangePDA(changeId)
+      );
+      assert.ok(pendingChange.stakingPool.equals(stakingPoolPDA));
+      assert.equal(
+        pendingChange.eta.sub(pendingChange.queuedAt).toString(),
+        poolBefore.timelockDelay.toString()
+      );
+
+      const poolAfter = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(poolAfter.normalizationK.toString(), "250", "Normalization_k should not change before eta");
+      assert.equal(poolAfter.changeCount.toString(), changeId.addn(1).toString());
+    });
 
-      // Update normalization_k to a new value
-      const newK = new BN(500);
+    it("should fail to execute a change before its eta", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const changeId = pool.changeCount.subn(1);
+      try {
+        await program.methods
+          .executeParameterChange(changeId)
+          .accounts({
+            authority: authority.publicKey,
+            stakingPool: stakingPoolPDA,
+            pendingChange: getPendingChangePDA(changeId),
+            stakeMint: stakeMint,
+            treasuryTokenAccount: null,
+            buybackTokenAccount: null,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have thrown an error");
+      } catch (error) {
+        assert.include(error.toString(), "TimelockNotExpired");
+      }
+    });
+
+    it("should cancel a queued change and close its account", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const changeId = pool.changeCount.subn(1);
       await program.methods
-        .updateNormalizationK(newK)
+        .cancelParameterChange(changeId)
         .accounts({
           authority: authority.publicKey,
           stakingPool: stakingPoolPDA,
+          pendingChange: getPendingChangePDA(changeId),
         })
         .signers([authority])
         .rpc();
 
-      // Verify the update
-      const poolAfter = await program.account.stakingPool.fetch(stakingPoolPDA);
-      assert.equal(poolAfter.normalizationK.toString(), "500", "Normalization_k should be updated to 500");
+      const closed = await provider.connection.getAccountInfo(
+        getPendingChangePDA(changeId)
+      );
+      assert.isNull(closed);
     });
 
-    it("should fail when non-authority tries to update normalization_k", async () => {
+    it("should fail when non-authority queues a change", async () => {
+      try {
+        await queueChange({ normalizationK: { newK: new BN(300) } }, user1);
+        assert.fail("Should have thrown an error");
+      } catch (error) {
+        assert.include(error.toString(), "Unauthorized");
+      }
+    });
+
+    it("should fail when trying to queue normalization_k of zero", async () => {
+      try {
+        await queueChange({ normalizationK: { newK: new BN(0) } });
+        assert.fail("Should have thrown an error");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidNormalizationK");
+      }
+    });
+
+    it("should reject invalid tier weights, penalty and schedule", async () => {
+      const invalidChanges: [any, string][] = [
+        [{ tierWeights: { weights: [10, 0, 20, 30] } }, "InvalidTierWeights"],
+        [{ maxPenalty: { percent: 101 } }, "InvalidPenalty"],
+        [
+          { emissionSchedule: { schedule: [...Array(11).fill(new BN(2)), new BN(1)] } },
+          "InvalidEmissionSchedule",
+        ],
+      ];
+
+      for (const [change, errorName] of invalidChanges) {
+        try {
+          await queueChange(change);
+          assert.fail("Should have thrown an error");
+        } catch (error) {
+          assert.include(error.toString(), errorName);
+        }
+      }
+    });
+
+    it("should reject a daily rate override for the current day", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      try {
+        await queueChange({
+          dailyRate: { dayIndex: pool.rateSnapshot.dayIndex, rate: new BN(1000) },
+        });
+        assert.fail("Should have thrown an error");
+      } catch (error) {
+        assert.include(error.toString(), "RetroactiveRateChange");
+      }
+    });
+
+    it("should queue a daily rate override for a future day", async () => {
+      const changeId = await queueChange({
+        dailyRate: { dayIndex: new BN(10), rate: new BN(1000) },
+      });
+
+      // Nothing is overridden until execution, the computed rate stays intact
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(pool.dailyRateOverrides.length, 0);
+
+      await program.methods
+        .cancelParameterChange(changeId)
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: getPendingChangePDA(changeId),
+        })
+        .signers([authority])
+        .rpc();
+    });
+
+    it("should reject daily rate overrides outside of the program", async () => {
+      try {
+        await queueChange({
+          dailyRate: { dayIndex: new BN(370), rate: new BN(1000) },
+        });
+        assert.fail("Should have thrown an error");
+      } catch (error) {
+        assert.include(error.toString(), "DayIndexOutOfBounds");
+      }
+    });
+
+    it("should reject fee routing that does not add up to 10000 bps", async () => {
+      try {
+        await queueChange({
+          feeRouting: {
+            feeSplit: { treasuryBps: 5000, buybackBps: 0, rewardVaultBps: 4000 },
+            buyback: anchor.web3.PublicKey.default,
+          },
+        });
+        assert.fail("Should have thrown an error");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidFeeSplit");
+      }
+    });
+
+    it("should require a buyback wallet when routing fees to buyback", async () => {
+      try {
+        await queueChange({
+          feeRouting: {
+            feeSplit: { treasuryBps: 5000, buybackBps: 5000, rewardVaultBps: 0 },
+            buyback: anchor.web3.PublicKey.default,
+          },
+        });
+        assert.fail("Should have thrown an error");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidFeeDestination");
+      }
+    });
+
+    it("should queue a treasury change", async () => {
+      const changeId = await queueChange({
+        treasury: { treasury: treasuryRewardAccount },
+      });
+
+      const pendingChange = await program.account.pendingChange.fetch(
+        getPendingChangePDA(changeId)
+      );
+      assert.ok(pendingChange.change.treasury.treasury.equals(treasuryRewardAccount));
+
+      await program.methods
+        .cancelParameterChange(changeId)
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: getPendingChangePDA(changeId),
+        })
+        .signers([authority])
+        .rpc();
+    });
+  });
+
+  describe("excess reward withdrawal", () => {
+    it("should fail to queue more than the unallocated surplus", async () => {
+      try {
+        await program.methods
+          .queueExcessWithdrawal(REWARD_POOL_AMOUNT)
+          .accounts({
+            authority: authority.publicKey,
+            stakingPool: stakingPoolPDA,
+            rewardVault: rewardVaultPDA,
+            stakeMint: stakeMint,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have thrown an error");
+      } catch (error) {
+        assert.include(error.toString(), "ExceedsExcessRewards");
+      }
+    });
+
+    it("should fail when non-authority queues a withdrawal", async () => {
       try {
         await program.methods
-          .updateNormalizationK(new BN(300))
+          .queueExcessWithdrawal(new BN(1))
           .accounts({
             authority: user1.publicKey,
             stakingPool: stakingPoolPDA,
+            rewardVault: rewardVaultPDA,
+            stakeMint: stakeMint,
           })
           .signers([user1])
           .rpc();
         assert.fail("Should have thrown an error");
       } catch (error) {
-        assert.include(error.toString(), "Unauthorized");
+        assert.isDefined(error);
       }
     });
 
-    it("should fail when trying to set normalization_k to zero", async () => {
+    it("should queue a withdrawal and enforce the timelock", async () => {
+      const amount = new BN(1);
+      await program.methods
+        .queueExcessWithdrawal(amount)
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          rewardVault: rewardVaultPDA,
+          stakeMint: stakeMint,
+        })
+        .signers([authority])
+        .rpc();
+
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(pool.pendingWithdrawalAmount.toString(), amount.toString());
+      assert.isAbove(pool.pendingWithdrawalEta.toNumber(), 0);
+
       try {
         await program.methods
-          .updateNormalizationK(new BN(0))
+          .withdrawExcessRewards(amount)
           .accounts({
             authority: authority.publicKey,
             stakingPool: stakingPoolPDA,
+            rewardVault: rewardVaultPDA,
+            treasuryTokenAccount: treasuryRewardAccount,
+            stakeMint: stakeMint,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
           })
           .signers([authority])
           .rpc();
         assert.fail("Should have thrown an error");
       } catch (error) {
-        assert.include(error.toString(), "InvalidNormalizationK");
+        assert.include(error.toString(), "TimelockNotExpired");
       }
     });
 
-    it("should update daily rates after changing normalization_k", async () => {
-      // Get pool state before update
-      const poolBefore = await program.account.stakingPool.fetch(stakingPoolPDA);
-      const ratesBefore = poolBefore.dailyRates.slice(0, 5);
-      
-      // Update normalization_k back to original value
-      const originalK = new BN(250);
+    it("should cancel a queued withdrawal", async () => {
       await program.methods
-        .updateNormalizationK(originalK)
+        .cancelExcessWithdrawal()
         .accounts({
           authority: authority.publicKey,
           stakingPool: stakingPoolPDA,
@@ -2394,15 +2756,2155 @@ describe("staking_program", () => {
         .signers([authority])
         .rpc();
 
-      // Get pool state after update
-      const poolAfter = await program.account.stakingPool.fetch(stakingPoolPDA);
-      
-      // Verify normalization_k is updated
-      assert.equal(poolAfter.normalizationK.toString(), "250", "Normalization_k should be back to 250");
-      
-      // Daily rates should be recalculated (they might be the same if no other state changed)
-      // but the update_daily_rate function should have been called
-      assert.isDefined(poolAfter.dailyRates);
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(pool.pendingWithdrawalAmount.toNumber(), 0);
+
+      try {
+        await program.methods
+          .cancelExcessWithdrawal()
+          .accounts({
+            authority: authority.publicKey,
+            stakingPool: stakingPoolPDA,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have thrown an error");
+      } catch (error) {
+        assert.include(error.toString(), "NoPendingWithdrawal");
+      }
+    });
+  });
+
+  describe("penalty redistribution", () => {
+    it("should record the weighted amount on stake entries", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const stakeEntry = await program.account.stakeEntry.fetch(user1StakeEntry0PDA);
+      const tierIndex = [3, 6, 9, 12].indexOf(stakeEntry.durationMonths);
+
+      assert.equal(
+        stakeEntry.weightedAmount.toString(),
+        stakeEntry.amount.muln(pool.tierWeights[tierIndex]).toString()
+      );
+    });
+
+    it("should default to routing penalties by the fee split", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.deepEqual(pool.penaltyMode, { route: {} });
+      assert.isTrue(pool.totalWeightedStake.gtn(0));
+    });
+
+    it("should queue a switch to redistribution mode", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const changeId = pool.changeCount;
+      const [pendingChangePDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          changeId.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .queueParameterChange({ penaltyMode: { mode: { redistribute: {} } } })
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      const pendingChange = await program.account.pendingChange.fetch(pendingChangePDA);
+      assert.deepEqual(pendingChange.change.penaltyMode.mode, { redistribute: {} });
+
+      await program.methods
+        .cancelParameterChange(changeId)
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+        })
+        .signers([authority])
+        .rpc();
+    });
+  });
+  describe("boosts", () => {
+    const getBoostPDA = (user: anchor.web3.PublicKey) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("boost"), user.toBuffer(), stakingPoolPDA.toBuffer()],
+        program.programId
+      )[0];
+
+    it("should start with boosts disabled", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.ok(pool.boostConfig.nftCollection.equals(anchor.web3.PublicKey.default));
+      assert.ok(pool.boostConfig.boostMint.equals(anchor.web3.PublicKey.default));
+      assert.equal(pool.boostConfig.maxBoostBps, 0);
+
+      const userStaking = await program.account.userStakingAccount.fetch(user1StakingAccountPDA);
+      assert.equal(userStaking.boostBps, 0);
+    });
+
+    it("should reject a boost config above the maximum boost", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const [pendingChangePDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          pool.changeCount.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .queueParameterChange({
+            boostConfig: {
+              config: {
+                nftCollection: anchor.web3.PublicKey.default,
+                nftBoostBps: 0,
+                boostMint: stakeMint,
+                tokenBoostBps: 100,
+                tokenBoostUnit: new BN(0),
+                maxBoostBps: 20_000,
+              },
+            },
+          })
+          .accounts({
+            authority: authority.publicKey,
+            stakingPool: stakingPoolPDA,
+            pendingChange: pendingChangePDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidBoostConfig");
+      }
+    });
+
+    it("should fail to lock boost tokens when no boost mint is configured", async () => {
+      const stakeEntries = [user1StakeEntry0PDA, user1StakeEntry1PDA];
+
+      try {
+        await program.methods
+          .lockBoostTokens(new BN(1_000_000_000))
+          .accounts({
+            user: user1.publicKey,
+            stakingPool: stakingPoolPDA,
+            userStakingAccount: user1StakingAccountPDA,
+            boostAccount: getBoostPDA(user1.publicKey),
+            boostMint: stakeMint,
+            userBoostTokenAccount: user1StakeAccount,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+          })
+          .remainingAccounts(
+            stakeEntries.map((pubkey) => ({ pubkey, isWritable: true, isSigner: false }))
+          )
+          .signers([user1])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "BoostNotConfigured");
+      }
+    });
+
+    it("should fail to attach an NFT when no collection is configured", async () => {
+      const boostPDA = getBoostPDA(user1.publicKey);
+      const [nftVaultPDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("boost_nft_vault"), boostPDA.toBuffer()],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .attachBoostNft()
+          .accounts({
+            user: user1.publicKey,
+            stakingPool: stakingPoolPDA,
+            userStakingAccount: user1StakingAccountPDA,
+            boostAccount: boostPDA,
+            nftMint: stakeMint,
+            userNftAccount: user1StakeAccount,
+            nftVault: nftVaultPDA,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([user1])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "BoostNotConfigured");
+      }
+    });
+
+    it("should fail to refresh the boost of a user without a boost account", async () => {
+      const stakeEntries = [user1StakeEntry0PDA, user1StakeEntry1PDA];
+
+      try {
+        await program.methods
+          .refreshBoost()
+          .accounts({
+            stakingPool: stakingPoolPDA,
+            userStakingAccount: user1StakingAccountPDA,
+            boostAccount: getBoostPDA(user1.publicKey),
+          })
+          .remainingAccounts(
+            stakeEntries.map((pubkey) => ({ pubkey, isWritable: true, isSigner: false }))
+          )
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "AccountNotInitialized");
+      }
+    });
+  });
+  describe("voting power", () => {
+    const MAX_LOCK_SECONDS = 12 * 30 * 86400;
+
+    it("should derive voting power from amount and remaining lock", async () => {
+      const userStaking = await program.account.userStakingAccount.fetch(user1StakingAccountPDA);
+      const stakeEntries = [];
+      for (let i = 0; i < userStaking.stakeCount.toNumber(); i++) {
+        stakeEntries.push(await getStakeEntryPDA(program, user1.publicKey, stakingPoolPDA, i));
+      }
+
+      const atTime = Math.floor(Date.now() / 1000) + 3600;
+      const votingPower = await program.methods
+        .getVotingPower(user1.publicKey, new BN(atTime))
+        .accounts({ stakingPool: stakingPoolPDA })
+        .remainingAccounts(
+          stakeEntries.map((pubkey) => ({ pubkey, isWritable: false, isSigner: false }))
+        )
+        .view();
+
+      let expected = new BN(0);
+      for (const pda of stakeEntries) {
+        const entry = await program.account.stakeEntry.fetch(pda);
+        const endTime = entry.startTime.toNumber() + entry.durationMonths * 30 * 86400;
+        if (!entry.isActive || atTime >= endTime) continue;
+        expected = expected.add(entry.amount.muln(endTime - atTime).divn(MAX_LOCK_SECONDS));
+      }
+
+      // Each entry may round down by one
+      assert.isTrue(expected.sub(votingPower).abs().lten(stakeEntries.length));
+    });
+
+    it("should keep the pool total as an upper bound of user voting power", async () => {
+      const atTime = new BN(Math.floor(Date.now() / 1000) + 3600);
+      const total = await program.methods
+        .getTotalVotingPower(atTime)
+        .accounts({ stakingPool: stakingPoolPDA })
+        .view();
+
+      const userPower = await program.methods
+        .getVotingPower(user1.publicKey, atTime)
+        .accounts({ stakingPool: stakingPoolPDA })
+        .remainingAccounts(
+          [user1StakeEntry0PDA].map((pubkey) => ({ pubkey, isWritable: false, isSigner: false }))
+        )
+        .view();
+
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.isTrue(pool.veSlope.gtn(0));
+      assert.isTrue(total.gte(userPower));
+    });
+
+    it("should reject voting power queries in the past", async () => {
+      try {
+        await program.methods
+          .getTotalVotingPower(new BN(0))
+          .accounts({ stakingPool: stakingPoolPDA })
+          .view();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidVotingPowerTime");
+      }
+    });
+  });
+  describe("voter weight add-in", () => {
+    it("should fail to update a voter weight record before a realm is configured", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.ok(pool.governanceRealm.equals(anchor.web3.PublicKey.default));
+
+      const [voterWeightRecordPDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("voter-weight-record"),
+          pool.governanceRealm.toBuffer(),
+          pool.governingTokenMint.toBuffer(),
+          user1.publicKey.toBuffer(),
+        ],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .updateVoterWeightRecord()
+          .accounts({
+            user: user1.publicKey,
+            stakingPool: stakingPoolPDA,
+            voterWeightRecord: voterWeightRecordPDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .remainingAccounts([
+            { pubkey: user1StakeEntry0PDA, isWritable: false, isSigner: false },
+          ])
+          .signers([user1])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "GovernanceNotConfigured");
+      }
+    });
+
+    it("should queue a governance realm change", async () => {
+      const realm = anchor.web3.Keypair.generate().publicKey;
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const [pendingChangePDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          pool.changeCount.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .queueParameterChange({ governance: { realm, governingTokenMint: stakeMint } })
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      const pendingChange = await program.account.pendingChange.fetch(pendingChangePDA);
+      assert.ok(pendingChange.change.governance.realm.equals(realm));
+      assert.ok(pendingChange.change.governance.governingTokenMint.equals(stakeMint));
+
+      await program.methods
+        .cancelParameterChange(pool.changeCount)
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+        })
+        .signers([authority])
+        .rpc();
+    });
+  });
+  describe("stake_for", () => {
+    it("should stake on behalf of a beneficiary", async () => {
+      const beneficiary = anchor.web3.Keypair.generate();
+      const beneficiaryStakingAccountPDA = await getUserStakingAccountPDA(
+        program,
+        beneficiary.publicKey,
+        stakingPoolPDA
+      );
+      const beneficiaryStakeEntryPDA = await getStakeEntryPDA(
+        program,
+        beneficiary.publicKey,
+        stakingPoolPDA,
+        0
+      );
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(
+          beneficiary.publicKey,
+          anchor.web3.LAMPORTS_PER_SOL
+        )
+      );
+      await program.methods
+        .setStakeForFunder(authority.publicKey)
+        .accounts({
+          user: beneficiary.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: beneficiaryStakingAccountPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([beneficiary])
+        .rpc();
+      await mintTo(
+        provider.connection,
+        authority,
+        stakeMint,
+        authorityTokenAccount,
+        authority,
+        BigInt(STAKE_AMOUNT.toString()),
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const funderBalanceBefore = await getAccount(
+        provider.connection,
+        authorityTokenAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      await program.methods
+        .stakeFor(beneficiary.publicKey, STAKE_AMOUNT, 6, [])
+        .accounts({
+          funder: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: beneficiaryStakingAccountPDA,
+          stakeEntry: beneficiaryStakeEntryPDA,
+          funderTokenAccount: authorityTokenAccount,
+          stakeVault: stakeVaultPDA,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([authority])
+        .rpc();
+
+      const funderBalanceAfter = await getAccount(
+        provider.connection,
+        authorityTokenAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const stakeEntry = await program.account.stakeEntry.fetch(beneficiaryStakeEntryPDA);
+      const userStakingAccount = await program.account.userStakingAccount.fetch(
+        beneficiaryStakingAccountPDA
+      );
+
+      assert.equal(
+        (funderBalanceBefore.amount - funderBalanceAfter.amount).toString(),
+        STAKE_AMOUNT.toString()
+      );
+      assert.ok(stakeEntry.owner.equals(beneficiary.publicKey));
+      assert.ok(stakeEntry.amount.eq(STAKE_AMOUNT));
+      assert.equal(stakeEntry.durationMonths, 6);
+      assert.ok(userStakingAccount.owner.equals(beneficiary.publicKey));
+      assert.equal(userStakingAccount.stakeCount.toNumber(), 1);
+    });
+
+    it("should let a wallet approve a funder for stake_for", async () => {
+      await program.methods
+        .setStakeForFunder(authority.publicKey)
+        .accounts({
+          user: user2.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: user2StakingAccountPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user2])
+        .rpc();
+
+      const userStakingAccount = await program.account.userStakingAccount.fetch(
+        user2StakingAccountPDA
+      );
+      assert.ok(userStakingAccount.stakeForFunder.equals(authority.publicKey));
+    });
+
+    it("should fail when the stake entry does not belong to the beneficiary", async () => {
+      const otherStakeEntryPDA = await getStakeEntryPDA(
+        program,
+        anchor.web3.Keypair.generate().publicKey,
+        stakingPoolPDA,
+        0
+      );
+
+      try {
+        await program.methods
+          .stakeFor(user2.publicKey, STAKE_AMOUNT, 3, [])
+          .accounts({
+            funder: authority.publicKey,
+            stakingPool: stakingPoolPDA,
+            userStakingAccount: user2StakingAccountPDA,
+            stakeEntry: otherStakeEntryPDA,
+            funderTokenAccount: authorityTokenAccount,
+            stakeVault: stakeVaultPDA,
+            stakeMint: stakeMint,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+            rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidStakeIndex");
+      }
+    });
+
+    it("should reject stakes for a wallet that has not approved the funder", async () => {
+      try {
+        await program.methods
+          .stakeFor(user1.publicKey, STAKE_AMOUNT, 3, [])
+          .accounts({
+            funder: authority.publicKey,
+            stakingPool: stakingPoolPDA,
+            userStakingAccount: user1StakingAccountPDA,
+            stakeEntry: await getStakeEntryPDA(program, user1.publicKey, stakingPoolPDA, 0),
+            funderTokenAccount: authorityTokenAccount,
+            stakeVault: stakeVaultPDA,
+            stakeMint: stakeMint,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+            rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "StakeForNotApproved");
+      }
+    });
+  });
+  describe("position NFTs", () => {
+    let stakeIndex: number;
+    let stakeEntryPDA: anchor.web3.PublicKey;
+    let positionMintPDA: anchor.web3.PublicKey;
+    let positionTokenAccount: anchor.web3.PublicKey;
+
+    before(async () => {
+      await mintTo(
+        provider.connection,
+        authority,
+        stakeMint,
+        authorityTokenAccount,
+        authority,
+        BigInt(STAKE_AMOUNT.toString()),
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const userStaking = await program.account.userStakingAccount.fetchNullable(
+        user2StakingAccountPDA
+      );
+      stakeIndex = userStaking ? userStaking.stakeCount.toNumber() : 0;
+      stakeEntryPDA = await getStakeEntryPDA(program, user2.publicKey, stakingPoolPDA, stakeIndex);
+
+      await program.methods
+        .stakeFor(user2.publicKey, STAKE_AMOUNT, 3, [])
+        .accounts({
+          funder: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: user2StakingAccountPDA,
+          stakeEntry: stakeEntryPDA,
+          funderTokenAccount: authorityTokenAccount,
+          stakeVault: stakeVaultPDA,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([authority])
+        .rpc();
+
+      [positionMintPDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("position_mint"), stakeEntryPDA.toBuffer()],
+        program.programId
+      );
+      positionTokenAccount = getAssociatedTokenAddressSync(
+        positionMintPDA,
+        user2.publicKey,
+        false,
+        TOKEN_2022_PROGRAM_ID
+      );
+    });
+
+    it("should mint a position NFT for a stake", async () => {
+      await program.methods
+        .mintPositionNft(new BN(stakeIndex))
+        .accounts({
+          user: user2.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: user2StakingAccountPDA,
+          stakeEntry: stakeEntryPDA,
+          positionMint: positionMintPDA,
+          positionTokenAccount,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([user2])
+        .rpc();
+
+      const stakeEntry = await program.account.stakeEntry.fetch(stakeEntryPDA);
+      assert.ok(stakeEntry.positionMint.equals(positionMintPDA));
+
+      const nftAccount = await getAccount(
+        provider.connection,
+        positionTokenAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      assert.equal(nftAccount.amount.toString(), "1");
+    });
+
+    it("should not mint a second position NFT for the same stake", async () => {
+      try {
+        await program.methods
+          .mintPositionNft(new BN(stakeIndex))
+          .accounts({
+            user: user2.publicKey,
+            stakingPool: stakingPoolPDA,
+            userStakingAccount: user2StakingAccountPDA,
+            stakeEntry: stakeEntryPDA,
+            positionMint: positionMintPDA,
+            positionTokenAccount,
+            stakeMint: stakeMint,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          })
+          .signers([user2])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "PositionAlreadyMinted");
+      }
+    });
+
+    it("should require the position NFT to unstake", async () => {
+      try {
+        await program.methods
+          .unstake(new BN(stakeIndex))
+          .accounts({
+            user: user2.publicKey,
+            stakingPool: stakingPoolPDA,
+            userStakingAccount: user2StakingAccountPDA,
+            stakeEntry: stakeEntryPDA,
+            userTokenAccount: user2StakeAccount,
+            userRewardAccount: user2StakeAccount,
+            stakeVault: stakeVaultPDA,
+            rewardVault: rewardVaultPDA,
+            treasuryTokenAccount: treasuryRewardAccount,
+            positionTokenAccount: null,
+            positionMint: null,
+            stakeMint: stakeMint,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          })
+          .signers([user2])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "PositionTokenized");
+      }
+    });
+
+    it("should let the holder unstake and burn the position NFT", async () => {
+      await program.methods
+        .unstake(new BN(stakeIndex))
+        .accounts({
+          user: user2.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: user2StakingAccountPDA,
+          stakeEntry: stakeEntryPDA,
+          userTokenAccount: user2StakeAccount,
+          userRewardAccount: user2StakeAccount,
+          stakeVault: stakeVaultPDA,
+          rewardVault: rewardVaultPDA,
+          treasuryTokenAccount: treasuryRewardAccount,
+          positionTokenAccount,
+          positionMint: positionMintPDA,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([user2])
+        .rpc();
+
+      const stakeEntry = await program.account.stakeEntry.fetch(stakeEntryPDA);
+      assert.isFalse(stakeEntry.isActive);
+
+      const nftAccount = await getAccount(
+        provider.connection,
+        positionTokenAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      assert.equal(nftAccount.amount.toString(), "0");
+    });
+  });
+  describe("liquid staking", () => {
+    let liquidMintPDA: anchor.web3.PublicKey;
+    let user1LiquidAccount: anchor.web3.PublicKey;
+
+    before(() => {
+      [liquidMintPDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("liquid_mint"), stakingPoolPDA.toBuffer()],
+        program.programId
+      );
+      user1LiquidAccount = getAssociatedTokenAddressSync(
+        liquidMintPDA,
+        user1.publicKey,
+        false,
+        TOKEN_2022_PROGRAM_ID
+      );
+    });
+
+    it("should fail to stake liquid before the receipt mint exists", async () => {
+      try {
+        await program.methods
+          .stakeLiquid(STAKE_AMOUNT, [])
+          .accounts({
+            user: user1.publicKey,
+            stakingPool: stakingPoolPDA,
+            liquidMint: stakeMint,
+            userTokenAccount: user1StakeAccount,
+            stakeVault: stakeVaultPDA,
+            rewardVault: rewardVaultPDA,
+            stakeMint: stakeMint,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+          })
+          .signers([user1])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "LiquidStakingDisabled");
+      }
+    });
+
+    it("should initialize the receipt mint", async () => {
+      await program.methods
+        .initializeLiquidMint()
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          liquidMint: liquidMintPDA,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([authority])
+        .rpc();
+
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.ok(pool.liquidMint.equals(liquidMintPDA));
+      assert.equal(pool.liquidExchangeRate.toString(), "1000000000000");
+    });
+
+    it("should mint receipts at the exchange rate and redeem them", async () => {
+      const amount = new BN(1_000_000_000);
+      const poolBefore = await program.account.stakingPool.fetch(stakingPoolPDA);
+
+      await program.methods
+        .stakeLiquid(amount, [])
+        .accounts({
+          user: user1.publicKey,
+          stakingPool: stakingPoolPDA,
+          liquidMint: liquidMintPDA,
+          userTokenAccount: user1StakeAccount,
+          userLiquidAccount: user1LiquidAccount,
+          stakeVault: stakeVaultPDA,
+          rewardVault: rewardVaultPDA,
+          stakeMint: stakeMint,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([user1])
+        .rpc();
+
+      const receipts = await getAccount(
+        provider.connection,
+        user1LiquidAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const expectedReceipts = amount
+        .mul(new BN("1000000000000"))
+        .div(poolBefore.liquidExchangeRate);
+      assert.equal(receipts.amount.toString(), expectedReceipts.toString());
+
+      const poolAfterStake = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.ok(poolAfterStake.liquidStaked.eq(poolBefore.liquidStaked.add(amount)));
+      assert.ok(poolAfterStake.totalStaked.eq(poolBefore.totalStaked.add(amount)));
+
+      const rate = await program.methods
+        .getLiquidExchangeRate()
+        .accounts({ stakingPool: stakingPoolPDA })
+        .view();
+      assert.ok(rate.eq(poolAfterStake.liquidExchangeRate));
+
+      await program.methods
+        .redeemLiquid(new BN(receipts.amount.toString()))
+        .accounts({
+          user: user1.publicKey,
+          stakingPool: stakingPoolPDA,
+          liquidMint: liquidMintPDA,
+          userTokenAccount: user1StakeAccount,
+          userLiquidAccount: user1LiquidAccount,
+          stakeVault: stakeVaultPDA,
+          rewardVault: rewardVaultPDA,
+          stakeMint: stakeMint,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([user1])
+        .rpc();
+
+      const poolAfterRedeem = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(poolAfterRedeem.liquidStaked.toString(), "0");
+    });
+
+    it("should unbond redeemed receipts before the withdrawal", async () => {
+      const amount = new BN(1_000_000_000);
+      const [liquidUnbondingPDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("liquid_unbonding"),
+          stakingPoolPDA.toBuffer(),
+          user1.publicKey.toBuffer(),
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .stakeLiquid(amount, [])
+        .accounts({
+          user: user1.publicKey,
+          stakingPool: stakingPoolPDA,
+          liquidMint: liquidMintPDA,
+          userTokenAccount: user1StakeAccount,
+          userLiquidAccount: user1LiquidAccount,
+          stakeVault: stakeVaultPDA,
+          rewardVault: rewardVaultPDA,
+          stakeMint: stakeMint,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([user1])
+        .rpc();
+
+      const receipts = await getAccount(
+        provider.connection,
+        user1LiquidAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const poolBefore = await program.account.stakingPool.fetch(stakingPoolPDA);
+
+      await program.methods
+        .requestRedeemLiquid(new BN(receipts.amount.toString()))
+        .accounts({
+          user: user1.publicKey,
+          stakingPool: stakingPoolPDA,
+          liquidMint: liquidMintPDA,
+          userLiquidAccount: user1LiquidAccount,
+          liquidUnbonding: liquidUnbondingPDA,
+          stakeVault: stakeVaultPDA,
+          rewardVault: rewardVaultPDA,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([user1])
+        .rpc();
+
+      // The redeemed stake leaves total_staked but stays in the stake vault
+      const unbonding = await program.account.liquidUnbonding.fetch(liquidUnbondingPDA);
+      const poolAfterRequest = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.ok(unbonding.owner.equals(user1.publicKey));
+      assert.equal(poolAfterRequest.liquidStaked.toString(), "0");
+      assert.equal(
+        poolAfterRequest.totalUnbonding.sub(poolBefore.totalUnbonding).toString(),
+        unbonding.amount.toString()
+      );
+
+      const balanceBefore = await getAccount(
+        provider.connection,
+        user1StakeAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      // Without a pool cooldown the unbonded amount is withdrawable right away
+      await program.methods
+        .withdrawLiquid()
+        .accounts({
+          user: user1.publicKey,
+          stakingPool: stakingPoolPDA,
+          liquidUnbonding: liquidUnbondingPDA,
+          userTokenAccount: user1StakeAccount,
+          stakeVault: stakeVaultPDA,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+        })
+        .signers([user1])
+        .rpc();
+
+      const balanceAfter = await getAccount(
+        provider.connection,
+        user1StakeAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      assert.equal(
+        (balanceAfter.amount - balanceBefore.amount).toString(),
+        unbonding.amount.toString()
+      );
+      const poolAfterWithdraw = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(
+        poolAfterWithdraw.totalUnbonding.toString(),
+        poolBefore.totalUnbonding.toString()
+      );
+      assert.isNull(await provider.connection.getAccountInfo(liquidUnbondingPDA));
+    });
+  });
+  describe("unbonding", () => {
+    let stakeIndex: number;
+    let stakeEntryPDA: anchor.web3.PublicKey;
+
+    const withdrawAccounts = () => ({
+      user: user2.publicKey,
+      stakingPool: stakingPoolPDA,
+      stakeEntry: stakeEntryPDA,
+      userTokenAccount: user2StakeAccount,
+      stakeVault: stakeVaultPDA,
+      positionTokenAccount: null,
+      positionMint: null,
+      stakeMint: stakeMint,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+      associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+    });
+
+    before(async () => {
+      await mintTo(
+        provider.connection,
+        authority,
+        stakeMint,
+        authorityTokenAccount,
+        authority,
+        BigInt(STAKE_AMOUNT.toString()),
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const userStaking = await program.account.userStakingAccount.fetch(user2StakingAccountPDA);
+      stakeIndex = userStaking.stakeCount.toNumber();
+      stakeEntryPDA = await getStakeEntryPDA(program, user2.publicKey, stakingPoolPDA, stakeIndex);
+
+      await program.methods
+        .stakeFor(user2.publicKey, STAKE_AMOUNT, 3, [])
+        .accounts({
+          funder: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: user2StakingAccountPDA,
+          stakeEntry: stakeEntryPDA,
+          funderTokenAccount: authorityTokenAccount,
+          stakeVault: stakeVaultPDA,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([authority])
+        .rpc();
+    });
+
+    it("should fail to withdraw an active stake", async () => {
+      try {
+        await program.methods
+          .withdraw(new BN(stakeIndex))
+          .accounts(withdrawAccounts())
+          .signers([user2])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "NoPendingUnbonding");
+      }
+    });
+
+    it("should move the stake out of total_staked on request and release it on withdraw", async () => {
+      const poolBefore = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(poolBefore.unbondingCooldown.toNumber(), 0);
+
+      await program.methods
+        .requestUnstake(new BN(stakeIndex))
+        .accounts({
+          user: user2.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: user2StakingAccountPDA,
+          stakeEntry: stakeEntryPDA,
+          userTokenAccount: user2StakeAccount,
+          userRewardAccount: user2RewardAccount,
+          stakeVault: stakeVaultPDA,
+          rewardVault: rewardVaultPDA,
+          treasuryTokenAccount: treasuryRewardAccount,
+          buybackTokenAccount: null,
+          positionTokenAccount: null,
+          positionMint: null,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([user2])
+        .rpc();
+
+      const stakeEntry = await program.account.stakeEntry.fetch(stakeEntryPDA);
+      const poolRequested = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.isFalse(stakeEntry.isActive);
+      assert.ok(stakeEntry.unbondingAmount.gtn(0));
+      assert.ok(stakeEntry.unbondingAmount.lte(STAKE_AMOUNT));
+      assert.equal(
+        poolBefore.totalStaked.sub(poolRequested.totalStaked).toString(),
+        STAKE_AMOUNT.toString()
+      );
+      assert.equal(
+        poolRequested.totalUnbonding.sub(poolBefore.totalUnbonding).toString(),
+        stakeEntry.unbondingAmount.toString()
+      );
+
+      const balanceBefore = await getAccount(
+        provider.connection,
+        user2StakeAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      await program.methods
+        .withdraw(new BN(stakeIndex))
+        .accounts(withdrawAccounts())
+        .signers([user2])
+        .rpc();
+
+      const balanceAfter = await getAccount(
+        provider.connection,
+        user2StakeAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const stakeEntryAfter = await program.account.stakeEntry.fetch(stakeEntryPDA);
+      const poolAfter = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(
+        (balanceAfter.amount - balanceBefore.amount).toString(),
+        stakeEntry.unbondingAmount.toString()
+      );
+      assert.equal(stakeEntryAfter.unbondingAmount.toNumber(), 0);
+      assert.equal(poolAfter.totalUnbonding.toString(), poolBefore.totalUnbonding.toString());
+    });
+
+    it("should reject an unbonding cooldown above the maximum", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const [pendingChangePDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          pool.changeCount.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .queueParameterChange({ unbondingCooldown: { cooldown: new BN(31 * 86400) } })
+          .accounts({
+            authority: authority.publicKey,
+            stakingPool: stakingPoolPDA,
+            pendingChange: pendingChangePDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidUnbondingCooldown");
+      }
+    });
+  });
+  describe("flexible tier", () => {
+    let stakeIndex: number;
+    let stakeEntryPDA: anchor.web3.PublicKey;
+
+    before(async () => {
+      await mintTo(
+        provider.connection,
+        authority,
+        stakeMint,
+        authorityTokenAccount,
+        authority,
+        BigInt(STAKE_AMOUNT.toString()),
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const userStaking = await program.account.userStakingAccount.fetch(user2StakingAccountPDA);
+      stakeIndex = userStaking.stakeCount.toNumber();
+      stakeEntryPDA = await getStakeEntryPDA(program, user2.publicKey, stakingPoolPDA, stakeIndex);
+    });
+
+    it("should stake without a lock and without voting power", async () => {
+      const poolBefore = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(poolBefore.flexibleWeight, 5);
+
+      await program.methods
+        .stakeFor(user2.publicKey, STAKE_AMOUNT, 0, [])
+        .accounts({
+          funder: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: user2StakingAccountPDA,
+          stakeEntry: stakeEntryPDA,
+          funderTokenAccount: authorityTokenAccount,
+          stakeVault: stakeVaultPDA,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([authority])
+        .rpc();
+
+      const stakeEntry = await program.account.stakeEntry.fetch(stakeEntryPDA);
+      const poolAfter = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(stakeEntry.durationMonths, 0);
+      assert.ok(stakeEntry.isActive);
+      assert.equal(poolAfter.veSlope.toString(), poolBefore.veSlope.toString());
+      assert.equal(
+        poolAfter.totalStaked.sub(poolBefore.totalStaked).toString(),
+        STAKE_AMOUNT.toString()
+      );
+    });
+
+    it("should unstake right away without a penalty", async () => {
+      const treasuryBefore = await getAccount(
+        provider.connection,
+        treasuryRewardAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const balanceBefore = await getAccount(
+        provider.connection,
+        user2StakeAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      await program.methods
+        .unstake(new BN(stakeIndex))
+        .accounts({
+          user: user2.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: user2StakingAccountPDA,
+          stakeEntry: stakeEntryPDA,
+          userTokenAccount: user2StakeAccount,
+          userRewardAccount: user2RewardAccount,
+          stakeVault: stakeVaultPDA,
+          rewardVault: rewardVaultPDA,
+          treasuryTokenAccount: treasuryRewardAccount,
+          buybackTokenAccount: null,
+          positionTokenAccount: null,
+          positionMint: null,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([user2])
+        .rpc();
+
+      const treasuryAfter = await getAccount(
+        provider.connection,
+        treasuryRewardAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const balanceAfter = await getAccount(
+        provider.connection,
+        user2StakeAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      assert.equal(treasuryAfter.amount.toString(), treasuryBefore.amount.toString());
+      assert.isTrue(balanceAfter.amount - balanceBefore.amount >= BigInt(STAKE_AMOUNT.toString()));
+      assert.isFalse((await program.account.stakeEntry.fetch(stakeEntryPDA)).isActive);
+    });
+
+    it("should reject a zero flexible weight", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const [pendingChangePDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          pool.changeCount.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .queueParameterChange({ flexibleWeight: { weight: 0 } })
+          .accounts({
+            authority: authority.publicKey,
+            stakingPool: stakingPoolPDA,
+            pendingChange: pendingChangePDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidTierWeights");
+      }
+    });
+  });
+  describe("auto-renew", () => {
+    let stakeEntryPDA: anchor.web3.PublicKey;
+    let stakeIndex: number;
+
+    const setAutoRenew = (index: number, entry: anchor.web3.PublicKey, autoRenew: boolean) =>
+      program.methods
+        .setAutoRenew(new BN(index), autoRenew)
+        .accounts({
+          user: user2.publicKey,
+          stakingPool: stakingPoolPDA,
+          stakeEntry: entry,
+          positionTokenAccount: null,
+          stakeMint: stakeMint,
+        })
+        .signers([user2])
+        .rpc();
+
+    const stakeForUser2 = async (durationMonths: number) => {
+      await mintTo(
+        provider.connection,
+        authority,
+        stakeMint,
+        authorityTokenAccount,
+        authority,
+        BigInt(STAKE_AMOUNT.toString()),
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      const userStaking = await program.account.userStakingAccount.fetch(user2StakingAccountPDA);
+      const index = userStaking.stakeCount.toNumber();
+      const entry = await getStakeEntryPDA(program, user2.publicKey, stakingPoolPDA, index);
+
+      await program.methods
+        .stakeFor(user2.publicKey, STAKE_AMOUNT, durationMonths, [])
+        .accounts({
+          funder: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: user2StakingAccountPDA,
+          stakeEntry: entry,
+          funderTokenAccount: authorityTokenAccount,
+          stakeVault: stakeVaultPDA,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([authority])
+        .rpc();
+
+      return { index, entry };
+    };
+
+    before(async () => {
+      ({ index: stakeIndex, entry: stakeEntryPDA } = await stakeForUser2(6));
+    });
+
+    it("should keep constant voting power while renewing", async () => {
+      const poolBefore = await program.account.stakingPool.fetch(stakingPoolPDA);
+
+      await setAutoRenew(stakeIndex, stakeEntryPDA, true);
+
+      const stakeEntry = await program.account.stakeEntry.fetch(stakeEntryPDA);
+      const poolAfter = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.isTrue(stakeEntry.autoRenew);
+      // 6 month lock = half of the max lock
+      assert.equal(
+        poolAfter.vePermanent.sub(poolBefore.vePermanent).toString(),
+        STAKE_AMOUNT.divn(2).toString()
+      );
+      assert.isTrue(poolAfter.veSlope.lt(poolBefore.veSlope));
+    });
+
+    it("should cancel the renewal before maturity", async () => {
+      const poolBefore = await program.account.stakingPool.fetch(stakingPoolPDA);
+
+      await setAutoRenew(stakeIndex, stakeEntryPDA, false);
+
+      const stakeEntry = await program.account.stakeEntry.fetch(stakeEntryPDA);
+      const poolAfter = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.isFalse(stakeEntry.autoRenew);
+      assert.equal(stakeEntry.renewalCount, 0);
+      assert.equal(
+        poolBefore.vePermanent.sub(poolAfter.vePermanent).toString(),
+        STAKE_AMOUNT.divn(2).toString()
+      );
+    });
+
+    it("should not renew a flexible stake", async () => {
+      const { index, entry } = await stakeForUser2(0);
+
+      try {
+        await setAutoRenew(index, entry, true);
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidDuration");
+      }
+    });
+  });
+  describe("matured stakes", () => {
+    it("should keep matured stakes out of the APY by default", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(pool.maturedWeight, 0);
+      // No lock has ended yet, the matured bucket is empty
+      assert.equal(pool.totalMatured.toNumber(), 0);
+    });
+
+    it("should queue a matured weight change", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const [pendingChangePDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          pool.changeCount.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .queueParameterChange({ maturedWeight: { weight: 5 } })
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      const pendingChange = await program.account.pendingChange.fetch(pendingChangePDA);
+      assert.equal(pendingChange.change.maturedWeight.weight, 5);
+
+      await program.methods
+        .cancelParameterChange(pool.changeCount)
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+        })
+        .signers([authority])
+        .rpc();
+    });
+  });
+  describe("claim epochs", () => {
+    it("should reject an anchor offset that is not whole days", async () => {
+      const otherMint = await createMint(
+        provider.connection,
+        authority,
+        authority.publicKey,
+        null,
+        DECIMALS,
+        undefined,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const [otherPoolPDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("staking_pool"), otherMint.toBuffer()],
+        program.programId
+      );
+      const [otherStakeVaultPDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("stake_vault"), otherMint.toBuffer()],
+        program.programId
+      );
+      const [otherRewardVaultPDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("reward_vault"), otherMint.toBuffer()],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .initialize(PROGRAM_END_DATE, { calendar: {} }, { weekly: {} }, new BN(3600))
+          .accounts({
+            authority: authority.publicKey,
+            stakingPool: otherPoolPDA,
+            stakeMint: otherMint,
+            stakeVault: otherStakeVaultPDA,
+            rewardVault: otherRewardVaultPDA,
+            treasuryAddress: treasuryRewardAccount,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+            rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidClaimEpoch");
+      }
+    });
+
+    it("should record the claim epoch of new stakes", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const entry = await program.account.stakeEntry.fetch(user1StakeEntry0PDA);
+      const epochLength = 7 * 86400;
+      const expected = Math.floor(
+        (entry.startTime.toNumber() - pool.programStartTime.toNumber() + pool.claimEpochOffset.toNumber()) /
+          epochLength
+      );
+      assert.isAtLeast(entry.lastClaimWeek.toNumber(), expected);
+    });
+  });
+  describe("accrual mode", () => {
+    it("should accrue whole days by default and queue pro-rata accrual", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.deepEqual(pool.accrualMode, { wholeDays: {} });
+
+      const [pendingChangePDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          pool.changeCount.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .queueParameterChange({ accrualMode: { mode: { proRata: {} } } })
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      const pendingChange = await program.account.pendingChange.fetch(pendingChangePDA);
+      assert.deepEqual(pendingChange.change.accrualMode.mode, { proRata: {} });
+
+      await program.methods
+        .cancelParameterChange(pool.changeCount)
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+        })
+        .signers([authority])
+        .rpc();
+    });
+  });
+  describe("staking caps", () => {
+    it("should report unlimited capacity while no caps are set", async () => {
+      const capacity = await program.methods
+        .getStakingCapacity()
+        .accounts({ stakingPool: stakingPoolPDA, userStakingAccount: user1StakingAccountPDA })
+        .view();
+
+      const unlimited = "18446744073709551615";
+      assert.equal(capacity.minStakeAmount.toNumber(), 0);
+      assert.equal(capacity.userRemaining.toString(), unlimited);
+      assert.equal(capacity.poolRemaining.toString(), unlimited);
+      assert.equal(capacity.tierRemaining.length, 5);
+      capacity.tierRemaining.forEach((remaining: BN) => assert.equal(remaining.toString(), unlimited));
+    });
+
+    it("should track the staked amount per tier", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const tierTotal = pool.tierStaked.reduce((sum: BN, staked: BN) => sum.add(staked), new BN(0));
+      assert.equal(tierTotal.toString(), pool.totalStaked.sub(pool.liquidStaked).toString());
+    });
+
+    it("should reject caps below the minimum stake", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const [pendingChangePDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          pool.changeCount.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .queueParameterChange({
+            stakingCaps: {
+              caps: {
+                minStakeAmount: new BN(1_000_000_000),
+                maxStakePerUser: new BN(500_000_000),
+                maxStakePerTier: [new BN(0), new BN(0), new BN(0), new BN(0), new BN(0)],
+                poolCap: new BN(0),
+              },
+            },
+          })
+          .accounts({
+            authority: authority.publicKey,
+            stakingPool: stakingPoolPDA,
+            pendingChange: pendingChangePDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidStakingCaps");
+      }
+    });
+  });
+  describe("allow-list and deny-list", () => {
+    const outsider = anchor.web3.Keypair.generate();
+
+    const sha256 = (...parts: Buffer[]) =>
+      createHash("sha256").update(Buffer.concat(parts)).digest();
+    const hashPair = (a: Buffer, b: Buffer) =>
+      Buffer.compare(a, b) <= 0 ? sha256(a, b) : sha256(b, a);
+
+    let merkleRoot: Buffer;
+    let user1Proof: number[][];
+
+    const findAccessEntryPDA = (seed: string, wallet: anchor.web3.PublicKey) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from(seed), stakingPoolPDA.toBuffer(), wallet.toBuffer()],
+        program.programId
+      )[0];
+
+    const findNextStakeEntryPDA = async (
+      wallet: anchor.web3.PublicKey,
+      userStakingAccount: anchor.web3.PublicKey
+    ) => {
+      const account = await program.account.userStakingAccount.fetch(userStakingAccount);
+      return anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("stake_entry"),
+          wallet.toBuffer(),
+          stakingPoolPDA.toBuffer(),
+          account.stakeCount.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      )[0];
+    };
+
+    before(async () => {
+      // Two leaf tree: user1 and a wallet outside the test
+      const leaf = sha256(user1.publicKey.toBuffer());
+      const sibling = sha256(outsider.publicKey.toBuffer());
+      merkleRoot = hashPair(leaf, sibling);
+      user1Proof = [Array.from(sibling)];
+
+      await program.methods
+        .updateAllowlist(true, Array.from(merkleRoot), anchor.web3.PublicKey.default)
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+        })
+        .signers([authority])
+        .rpc();
+    });
+
+    after(async () => {
+      await program.methods
+        .updateAllowlist(false, Array(32).fill(0), anchor.web3.PublicKey.default)
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+        })
+        .signers([authority])
+        .rpc();
+    });
+
+    it("should store the allow-list configuration", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.isTrue(pool.allowlistEnabled);
+      assert.deepEqual(Buffer.from(pool.allowlistRoot), merkleRoot);
+      assert.isTrue(pool.gatekeeper.equals(anchor.web3.PublicKey.default));
+    });
+
+    it("should reject a wallet without an entry or proof", async () => {
+      try {
+        await program.methods
+          .stake(STAKE_AMOUNT.div(new BN(10)), 3, [], null)
+          .accounts({
+            user: user2.publicKey,
+            stakingPool: stakingPoolPDA,
+            userStakingAccount: user2StakingAccountPDA,
+            stakeEntry: await findNextStakeEntryPDA(user2.publicKey, user2StakingAccountPDA),
+            userTokenAccount: user2StakeAccount,
+            stakeVault: stakeVaultPDA,
+            stakeMint: stakeMint,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          })
+          .signers([user2])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "NotAllowlisted");
+      }
+    });
+
+    it("should accept a Merkle proof", async () => {
+      const leaf = sha256(user1.publicKey.toBuffer());
+      assert.deepEqual(hashPair(leaf, Buffer.from(user1Proof[0])), merkleRoot);
+      const stakeEntry = await findNextStakeEntryPDA(user1.publicKey, user1StakingAccountPDA);
+
+      await program.methods
+        .stake(STAKE_AMOUNT.div(new BN(10)), 3, user1Proof, null)
+        .accounts({
+          user: user1.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: user1StakingAccountPDA,
+          stakeEntry,
+          userTokenAccount: user1StakeAccount,
+          stakeVault: stakeVaultPDA,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([user1])
+        .rpc();
+
+      const entry = await program.account.stakeEntry.fetch(stakeEntry);
+      assert.isTrue(entry.isActive);
+    });
+
+    it("should accept an allow-list entry added by the gatekeeper", async () => {
+      const allowlistEntry = findAccessEntryPDA("allowlist", user2.publicKey);
+
+      await program.methods
+        .addAllowlistEntry(user2.publicKey)
+        .accounts({
+          gatekeeper: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          allowlistEntry,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      const entry = await program.account.allowlistEntry.fetch(allowlistEntry);
+      assert.isTrue(entry.wallet.equals(user2.publicKey));
+
+      const stakeEntry = await findNextStakeEntryPDA(user2.publicKey, user2StakingAccountPDA);
+      await program.methods
+        .stake(STAKE_AMOUNT.div(new BN(10)), 3, [], null)
+        .accounts({
+          user: user2.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount: user2StakingAccountPDA,
+          stakeEntry,
+          userTokenAccount: user2StakeAccount,
+          stakeVault: stakeVaultPDA,
+          allowlistEntry,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([user2])
+        .rpc();
+
+      await program.methods
+        .removeAllowlistEntry(user2.publicKey)
+        .accounts({
+          gatekeeper: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          allowlistEntry,
+        })
+        .signers([authority])
+        .rpc();
+
+      assert.isNull(await provider.connection.getAccountInfo(allowlistEntry));
+    });
+
+    it("should only let the gatekeeper or authority manage entries", async () => {
+      try {
+        await program.methods
+          .addAllowlistEntry(user1.publicKey)
+          .accounts({
+            gatekeeper: user1.publicKey,
+            stakingPool: stakingPoolPDA,
+            allowlistEntry: findAccessEntryPDA("allowlist", user1.publicKey),
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([user1])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "Unauthorized");
+      }
+    });
+
+    it("should block claims of a denied wallet", async () => {
+      const denylistEntry = findAccessEntryPDA("denylist", user2.publicKey);
+
+      await program.methods
+        .addDenylistEntry(user2.publicKey)
+        .accounts({
+          gatekeeper: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          denylistEntry,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      try {
+        await program.methods
+          .claimAll()
+          .accounts({
+            user: user2.publicKey,
+            stakingPool: stakingPoolPDA,
+            userStakingAccount: user2StakingAccountPDA,
+            userRewardAccount: user2RewardAccount,
+            rewardVault: rewardVaultPDA,
+            stakeMint: stakeMint,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+            rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          })
+          .remainingAccounts([
+            { pubkey: user2StakeEntry0PDA, isWritable: true, isSigner: false },
+          ])
+          .signers([user2])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "WalletDenied");
+      }
+
+      await program.methods
+        .removeDenylistEntry(user2.publicKey)
+        .accounts({
+          gatekeeper: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          denylistEntry,
+        })
+        .signers([authority])
+        .rpc();
+
+      assert.isNull(await provider.connection.getAccountInfo(denylistEntry));
+    });
+  });
+  describe("referrals", () => {
+    let user1ReferrerPDA: anchor.web3.PublicKey;
+
+    const findNextStakeEntryPDA = async (
+      wallet: anchor.web3.PublicKey,
+      userStakingAccount: anchor.web3.PublicKey
+    ) => {
+      const account = await program.account.userStakingAccount.fetch(userStakingAccount);
+      return anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("stake_entry"),
+          wallet.toBuffer(),
+          stakingPoolPDA.toBuffer(),
+          account.stakeCount.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      )[0];
+    };
+
+    const stakeWithReferrer = async (
+      user: anchor.web3.Keypair,
+      userStakingAccount: anchor.web3.PublicKey,
+      userTokenAccount: anchor.web3.PublicKey,
+      referrer: anchor.web3.PublicKey,
+      referrerAccount: anchor.web3.PublicKey | null
+    ) => {
+      const stakeEntry = await findNextStakeEntryPDA(user.publicKey, userStakingAccount);
+      await program.methods
+        .stake(STAKE_AMOUNT.div(new BN(10)), 3, [], referrer)
+        .accounts({
+          user: user.publicKey,
+          stakingPool: stakingPoolPDA,
+          userStakingAccount,
+          stakeEntry,
+          userTokenAccount,
+          stakeVault: stakeVaultPDA,
+          referrerAccount,
+          stakeMint: stakeMint,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([user])
+        .rpc();
+      return stakeEntry;
+    };
+
+    before(async () => {
+      [user1ReferrerPDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("referrer"), stakingPoolPDA.toBuffer(), user1.publicKey.toBuffer()],
+        program.programId
+      );
+    });
+
+    it("should register a referrer", async () => {
+      await program.methods
+        .registerReferrer()
+        .accounts({
+          referrer: user1.publicKey,
+          stakingPool: stakingPoolPDA,
+          referrerAccount: user1ReferrerPDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([user1])
+        .rpc();
+
+      const referrerAccount = await program.account.referrerAccount.fetch(user1ReferrerPDA);
+      assert.isTrue(referrerAccount.referrer.equals(user1.publicKey));
+      assert.equal(referrerAccount.referralCount.toNumber(), 0);
+    });
+
+    it("should record the referrer on the stake entry", async () => {
+      const stakeEntry = await stakeWithReferrer(
+        user2,
+        user2StakingAccountPDA,
+        user2StakeAccount,
+        user1.publicKey,
+        user1ReferrerPDA
+      );
+
+      const entry = await program.account.stakeEntry.fetch(stakeEntry);
+      assert.isTrue(entry.referrer.equals(user1.publicKey));
+      assert.equal(entry.referralAccrued.toNumber(), 0);
+
+      const referrerAccount = await program.account.referrerAccount.fetch(user1ReferrerPDA);
+      assert.equal(referrerAccount.referralCount.toNumber(), 1);
+      assert.equal(referrerAccount.totalReferred.toString(), entry.amount.toString());
+    });
+
+    it("should reject self referral", async () => {
+      try {
+        await stakeWithReferrer(
+          user1,
+          user1StakingAccountPDA,
+          user1StakeAccount,
+          user1.publicKey,
+          user1ReferrerPDA
+        );
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidReferrer");
+      }
+    });
+
+    it("should reject an unregistered referrer", async () => {
+      try {
+        await stakeWithReferrer(
+          user1,
+          user1StakingAccountPDA,
+          user1StakeAccount,
+          user2.publicKey,
+          null
+        );
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "ReferrerNotRegistered");
+      }
+    });
+
+    it("should fail to claim without accrued referral rewards", async () => {
+      const user2Account = await program.account.userStakingAccount.fetch(user2StakingAccountPDA);
+      const referredEntry = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("stake_entry"),
+          user2.publicKey.toBuffer(),
+          stakingPoolPDA.toBuffer(),
+          user2Account.stakeCount.subn(1).toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      )[0];
+
+      try {
+        await program.methods
+          .claimReferralRewards()
+          .accounts({
+            referrer: user1.publicKey,
+            stakingPool: stakingPoolPDA,
+            referrerAccount: user1ReferrerPDA,
+            referrerRewardAccount: user1RewardAccount,
+            rewardVault: rewardVaultPDA,
+            stakeMint: stakeMint,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+            associatedTokenProgram: anchor.utils.token.ASSOCIATED_PROGRAM_ID,
+          })
+          .remainingAccounts([{ pubkey: referredEntry, isWritable: true, isSigner: false }])
+          .signers([user1])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "NoRewardsAvailable");
+      }
+    });
+
+    it("should reject a referral share above the maximum", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const [pendingChangePDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          pool.changeCount.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+      try {
+        await program.methods
+          .queueParameterChange({ referralBps: { bps: 2_001 } })
+          .accounts({
+            authority: authority.publicKey,
+            stakingPool: stakingPoolPDA,
+            pendingChange: pendingChangePDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Should have failed");
+      } catch (error) {
+        assert.include(error.toString(), "InvalidReferralBps");
+      }
+    });
+  });
+  describe("daily rate snapshots", () => {
+    it("should keep the inputs of the latest daily rate", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const snapshot = pool.rateSnapshot;
+      const dayIndex = snapshot.dayIndex.toNumber();
+
+      assert.isAtMost(dayIndex, pool.dailyRates.length - 1);
+      assert.equal(snapshot.totalStaked.toString(), pool.totalStaked.toString());
+      assert.isTrue(snapshot.earningStake.lte(snapshot.totalStaked));
+
+      // The stored rate follows from the snapshot: available / (earning + 1) * 10000
+      const expectedRate = snapshot.availableRewards
+        .div(snapshot.earningStake.addn(1))
+        .muln(10_000);
+      assert.equal(pool.dailyRates[dayIndex].toString(), expectedRate.toString());
+    });
+  });
+  describe("daily rate crank", () => {
+    it("should refresh today's rate without a tip", async () => {
+      await program.methods
+        .crankDailyRate()
+        .accounts({
+          cranker: user1.publicKey,
+          stakingPool: stakingPoolPDA,
+          rewardVault: rewardVaultPDA,
+          crankerTokenAccount: null,
+          stakeMint: stakeMint,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([user1])
+        .rpc();
+
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const today = Math.floor(
+        (Math.floor(Date.now() / 1000) - pool.programStartTime.toNumber()) / 86400
+      );
+      assert.equal(pool.rateSnapshot.dayIndex.toNumber(), today);
+      assert.deepEqual(pool.gapFillMode, { carryForward: {} });
+    });
+
+    it("should fund the crank budget apart from the rewards", async () => {
+      const amount = new BN(1_000_000_000);
+      const poolBefore = await program.account.stakingPool.fetch(stakingPoolPDA);
+
+      await program.methods
+        .fundCrankBudget(amount)
+        .accounts({
+          funder: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          funderTokenAccount: authorityTokenAccount,
+          rewardVault: rewardVaultPDA,
+          stakeMint: stakeMint,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([authority])
+        .rpc();
+
+      const poolAfter = await program.account.stakingPool.fetch(stakingPoolPDA);
+      assert.equal(
+        poolAfter.crankBudget.sub(poolBefore.crankBudget).toString(),
+        amount.toString()
+      );
+      assert.equal(poolAfter.totalFunded.toString(), poolBefore.totalFunded.toString());
+    });
+
+    it("should not tip a call that finalizes no days", async () => {
+      const balanceBefore = await getAccount(
+        provider.connection,
+        user1StakeAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      await program.methods
+        .crankDailyRate()
+        .accounts({
+          cranker: user1.publicKey,
+          stakingPool: stakingPoolPDA,
+          rewardVault: rewardVaultPDA,
+          crankerTokenAccount: user1StakeAccount,
+          stakeMint: stakeMint,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([user1])
+        .rpc();
+
+      const balanceAfter = await getAccount(
+        provider.connection,
+        user1StakeAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      assert.equal(balanceAfter.amount.toString(), balanceBefore.amount.toString());
+    });
+
+    it("should queue a gap fill mode change", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const changeId = pool.changeCount;
+      const [pendingChangePDA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          changeId.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+      await program.methods
+        .queueParameterChange({ gapFillMode: { mode: { zero: {} } } })
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      const pendingChange = await program.account.pendingChange.fetch(pendingChangePDA);
+      assert.deepEqual(pendingChange.change.gapFillMode.mode, { zero: {} });
+
+      await program.methods
+        .cancelParameterChange(changeId)
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+        })
+        .signers([authority])
+        .rpc();
+    });
+  });
+  describe("rate smoothing", () => {
+    const getPendingChangePDA = (changeId: BN) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("pending_change"),
+          stakingPoolPDA.toBuffer(),
+          changeId.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      )[0];
+
+    const smoothing = (overrides: object = {}) => ({
+      mode: { ema: {} },
+      emaDays: 7,
+      maxDailyChangeBps: 2_000,
+      minRate: new BN(0),
+      maxRate: new BN(0),
+      ...overrides,
+    });
+
+    it("should keep raw rates equal to daily rates while smoothing is off", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const dayIndex = pool.rateSnapshot.dayIndex.toNumber();
+
+      assert.deepEqual(pool.rateSmoothing.mode, { off: {} });
+      assert.equal(pool.rawDailyRates.length, pool.dailyRates.length);
+      assert.equal(
+        pool.rawDailyRates[dayIndex].toString(),
+        pool.dailyRates[dayIndex].toString()
+      );
+    });
+
+    it("should queue a rate smoothing change", async () => {
+      const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+      const changeId = pool.changeCount;
+      const pendingChangePDA = getPendingChangePDA(changeId);
+
+      await program.methods
+        .queueParameterChange({
+          rateSmoothing: { config: smoothing({ maxRate: new BN(1_000_000) }) },
+        })
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+
+      const pendingChange = await program.account.pendingChange.fetch(pendingChangePDA);
+      const config = pendingChange.change.rateSmoothing.config;
+      assert.deepEqual(config.mode, { ema: {} });
+      assert.equal(config.emaDays, 7);
+      assert.equal(config.maxDailyChangeBps, 2_000);
+      assert.equal(config.maxRate.toString(), "1000000");
+
+      await program.methods
+        .cancelParameterChange(changeId)
+        .accounts({
+          authority: authority.publicKey,
+          stakingPool: stakingPoolPDA,
+          pendingChange: pendingChangePDA,
+        })
+        .signers([authority])
+        .rpc();
+    });
+
+    it("should reject invalid rate smoothing", async () => {
+      const invalidConfigs = [
+        smoothing({ emaDays: 0 }),
+        smoothing({ minRate: new BN(2_000), maxRate: new BN(1_000) }),
+      ];
+
+      for (const config of invalidConfigs) {
+        const pool = await program.account.stakingPool.fetch(stakingPoolPDA);
+        try {
+          await program.methods
+            .queueParameterChange({ rateSmoothing: { config } })
+            .accounts({
+              authority: authority.publicKey,
+              stakingPool: stakingPoolPDA,
+              pendingChange: getPendingChangePDA(pool.changeCount),
+              systemProgram: anchor.web3.SystemProgram.programId,
+            })
+            .signers([authority])
+            .rpc();
+          assert.fail("Should have failed");
+        } catch (error) {
+          assert.include(error.toString(), "InvalidRateSmoothing");
+        }
+      }
     });
   });
 });